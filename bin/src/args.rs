@@ -21,12 +21,30 @@ pub enum Output {
 	Interpret,
 	#[strum(serialize = "render", serialize = "c")]
 	Render,
+	#[strum(serialize = "bytecode")]
+	Bytecode,
+	#[strum(serialize = "bf")]
+	Bf,
+	#[strum(serialize = "rust")]
+	Rust,
+	#[strum(serialize = "js")]
+	Js,
+	#[strum(serialize = "wasm")]
+	Wasm,
+	#[strum(serialize = "asm")]
+	Asm,
+	#[strum(serialize = "elf")]
+	Elf,
+	#[strum(serialize = "llvm")]
+	Llvm,
+	#[strum(serialize = "python", serialize = "py")]
+	Python,
 }
 
 /// A low level brainfuck runtime.
 #[derive(argh::FromArgs)]
 struct CommandLine {
-	/// read and run code from a given file
+	/// read and run code or bytecode from a given file
 	#[argh(option, short = 'f')]
 	file: Option<PathBuf>,
 
@@ -38,20 +56,99 @@ struct CommandLine {
 	#[argh(option, short = 'm', default = "Default::default()")]
 	mode: Mode,
 
-	/// whether to 'interpret' or 'render' to C (shorthand i/c)
+	/// whether to 'interpret', 'render' to C (shorthand i/c), write 'bytecode', a 'wasm' module or an x86-64 Linux 'elf' executable, or render to 'bf', 'rust', 'js', 'python', x86-64 'asm' or 'llvm' IR
 	#[argh(option, short = 'o', default = "Default::default()")]
 	output: Output,
 
 	/// an optional instruction limit for the interpreter
 	#[argh(option, short = 'l')]
 	limit: Option<u64>,
+
+	/// the optimization level from 0 to 3, defaults to 2
+	#[argh(option, short = 'O', default = "2")]
+	opt_level: u8,
+
+	/// print statistics about each optimization pass to stderr
+	#[argh(switch)]
+	stats: bool,
+
+	/// the line width when rendering to bf, or 0 to never wrap, defaults to 80
+	#[argh(option, default = "80")]
+	line_width: usize,
+
+	/// follow long runs of the same character with their length when rendering to bf
+	#[argh(switch)]
+	run_length_comments: bool,
+
+	/// check that the pointer stays in the data array when rendering to asm or elf
+	#[argh(switch)]
+	bounds_checks: bool,
+
+	#[argh(subcommand)]
+	command: Option<Subcommand>,
+}
+
+#[derive(argh::FromArgs)]
+#[argh(subcommand)]
+enum Subcommand {
+	Lint(LintCommand),
+	Fmt(FmtCommand),
+}
+
+/// Check code for likely mistakes, printing one JSON object per warning.
+#[derive(argh::FromArgs)]
+#[argh(subcommand, name = "lint")]
+struct LintCommand {
+	/// read code from a given file
+	#[argh(option, short = 'f')]
+	file: Option<PathBuf>,
+
+	/// read code from argv
+	#[argh(option, short = 'a')]
+	args: Option<String>,
+
+	/// whether to use 8/16/32 bit mode, defaults to 8
+	#[argh(option, short = 'm', default = "Default::default()")]
+	mode: Mode,
+}
+
+/// Format code, printing the result.
+#[derive(argh::FromArgs)]
+#[argh(subcommand, name = "fmt")]
+struct FmtCommand {
+	/// read code from a given file
+	#[argh(option, short = 'f')]
+	file: Option<PathBuf>,
+
+	/// read code from argv
+	#[argh(option, short = 'a')]
+	args: Option<String>,
+
+	/// the line width to wrap code at, defaults to 80
+	#[argh(option, default = "80")]
+	line_width: usize,
+}
+
+#[derive(Clone, Copy)]
+pub enum Command {
+	Run,
+	Lint,
+	Fmt,
 }
 
 pub struct Args {
+	pub command: Command,
 	pub mode: Mode,
 	pub output: Output,
 	pub code: Vec<u8>,
+	/// The file the code was read from, or `<args>`, for error messages.
+	pub source_name: String,
 	pub instruction_limit: Option<u64>,
+	pub opt_level: u8,
+	pub stats: bool,
+	pub line_width: Option<usize>,
+	pub run_length_comments: bool,
+	pub bounds_checks: bool,
 }
 
 impl Args {
@@ -62,22 +159,50 @@ impl Args {
 			mode,
 			output,
 			limit,
+			opt_level,
+			stats,
+			mut line_width,
+			run_length_comments,
+			bounds_checks,
+			command,
 		} = argh::from_env();
 
-		let code = match (file, args) {
+		let (command, file, args, mode) = match command {
+			None => (Command::Run, file, args, mode),
+			Some(_) if file.is_some() || args.is_some() => {
+				return Err(anyhow::anyhow!("code must be passed after the subcommand"));
+			}
+			Some(Subcommand::Lint(lint)) => (Command::Lint, lint.file, lint.args, lint.mode),
+			Some(Subcommand::Fmt(fmt)) => {
+				line_width = fmt.line_width;
+				(Command::Fmt, fmt.file, fmt.args, mode)
+			}
+		};
+
+		let (code, source_name) = match (file, args) {
 			(Some(_file), Some(_args)) => {
 				return Err(anyhow::anyhow!("both file and args cannot be provided"));
 			}
-			(Some(file), None) => std::fs::read(&file).context("could not open file")?,
-			(None, Some(args)) => args.into_bytes(),
-			(None, None) => vec![],
+			(Some(file), None) => (
+				std::fs::read(&file).context("could not open file")?,
+				file.display().to_string(),
+			),
+			(None, Some(args)) => (args.into_bytes(), "<args>".to_owned()),
+			(None, None) => (vec![], "<args>".to_owned()),
 		};
 
 		Ok(Self {
+			command,
 			mode,
 			output,
 			code,
+			source_name,
 			instruction_limit: limit,
+			opt_level,
+			stats,
+			line_width: (line_width != 0).then_some(line_width),
+			run_length_comments,
+			bounds_checks,
 		})
 	}
 }
@@ -0,0 +1,55 @@
+use std::fmt::Write as _;
+
+use bfirs::compile::Error;
+use bfirs::span::Position;
+
+/// Render a compile error like rustc does, with the offending brackets annotated in the source.
+pub fn render(error: &Error, source: &[u8], source_name: &str) -> String {
+	let (message, labels) = match error {
+		Error::UnmatchedStart(positions) => (
+			"unmatched loop start",
+			positions
+				.iter()
+				.map(|&position| (position, "this loop is never closed"))
+				.collect(),
+		),
+		Error::UnmatchedEnd(position) => (
+			"unmatched loop end",
+			vec![(*position, "this loop was never started")],
+		),
+	};
+
+	let gutter = labels
+		.iter()
+		.map(|(position, _)| position.line.to_string().len())
+		.max()
+		.unwrap_or(1);
+
+	let mut out = format!("error: {message}\n");
+	for (position, label) in labels {
+		let line = line_at(source, position);
+		let line = String::from_utf8_lossy(line);
+		// keep tabs so the caret lines up with the code above it
+		let padding: String =
+			String::from_utf8_lossy(&source[position.offset + 1 - position.column..position.offset])
+				.chars()
+				.map(|c| if c == '\t' { '\t' } else { ' ' })
+				.collect();
+
+		let _ = writeln!(out, "{:gutter$}--> {source_name}:{position}", "");
+		let _ = writeln!(out, "{:gutter$} |", "");
+		let _ = writeln!(out, "{:>gutter$} | {}", position.line, line.trim_end());
+		let _ = writeln!(out, "{:gutter$} | {padding}^ {label}", "");
+	}
+	out
+}
+
+/// Get the line that `position` is on, without its line ending.
+fn line_at(source: &[u8], position: Position) -> &[u8] {
+	let start = position.offset + 1 - position.column;
+	let end = source[start..]
+		.iter()
+		.position(|&byte| byte == b'\n')
+		.map_or(source.len(), |len| start + len);
+	&source[start..end]
+}
@@ -0,0 +1,39 @@
+use std::io::{self, Write};
+
+use bfirs::lint::Warning;
+
+/// Print each warning as a JSON object on its own line.
+pub fn print(warnings: &[Warning], source_name: &str, mut out: impl Write) -> io::Result<()> {
+	for warning in warnings {
+		let Warning { kind, span } = warning;
+		writeln!(
+			out,
+			r#"{{"kind":{},"message":{},"file":{},"line":{},"column":{},"end_line":{},"end_column":{},"offset":{},"length":{}}}"#,
+			json_string(kind.name()),
+			json_string(&kind.to_string()),
+			json_string(source_name),
+			span.start.line,
+			span.start.column,
+			span.end.line,
+			span.end.column,
+			span.start.offset,
+			span.range().len(),
+		)?;
+	}
+	out.flush()
+}
+
+fn json_string(string: &str) -> String {
+	let mut escaped = String::with_capacity(string.len() + 2);
+	escaped.push('"');
+	for c in string.chars() {
+		match c {
+			'"' => escaped.push_str("\\\""),
+			'\\' => escaped.push_str("\\\\"),
+			c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", u32::from(c))),
+			c => escaped.push(c),
+		}
+	}
+	escaped.push('"');
+	escaped
+}
@@ -1,16 +1,71 @@
+use std::io::Write as _;
+
 use anyhow::Context as _;
+use bfirs::compile::{
+	bytecode, OptimizeOptions, RenderAsmOptions, RenderBfOptions, RenderRustOptions,
+};
+use bfirs::format::FormatOptions;
 use bfirs::{InstructionStream, Interpreter};
 
 mod args;
-use args::{Mode, Output};
+mod diagnostic;
+mod lint;
+use args::{Args, Command, Mode, Output};
 
 fn main() -> anyhow::Result<()> {
-	let args = args::Args::from_env().context("parsing arguments")?;
+	let args = Args::from_env().context("parsing arguments")?;
+
+	if let Command::Fmt = args.command {
+		let options = FormatOptions::default().line_width(args.line_width.unwrap_or(usize::MAX));
+		let formatted = bfirs::format::format(&args.code, &options)
+			.unwrap_or_else(|error| compile_error(&error, &args));
+		return std::io::stdout()
+			.lock()
+			.write_all(&formatted)
+			.context("printing formatted code");
+	}
+
+	// bytecode is already compiled for a specific cell width
+	let bytecode_width = match bytecode::cell_width(&args.code) {
+		Ok(width) => Some(width),
+		Err(bytecode::Error::NotBytecode) => None,
+		Err(error) => return Err(error).context("loading bytecode"),
+	};
+	let mode = match bytecode_width {
+		None => args.mode,
+		Some(1) => Mode::U8,
+		Some(2) => Mode::U16,
+		Some(4) => Mode::U32,
+		Some(width) => anyhow::bail!("loading bytecode: unsupported cell width {width}"),
+	};
 
 	macro_rules! run_different_sizes {
 		($ty:ty) => {{
-			let code =
-				InstructionStream::optimized_from_code(args.code.into_iter()).context("optimizing")?;
+			if let Command::Lint = args.command {
+				let warnings =
+					bfirs::lint::lint::<$ty>(&args.code).unwrap_or_else(|error| compile_error(&error, &args));
+				return lint::print(&warnings, &args.source_name, std::io::stdout().lock())
+					.context("printing warnings");
+			}
+
+			let code = if bytecode_width.is_some() {
+				InstructionStream::<$ty>::from_bytecode(&args.code).context("loading bytecode")?
+			} else {
+				let mut code = InstructionStream::<$ty>::from_code_with_spans(args.code.iter().copied())
+					.unwrap_or_else(|error| compile_error(&error, &args));
+				let stats = code
+					.optimize_with(&mut OptimizeOptions::level(args.opt_level))
+					.context("optimizing")?;
+				if args.stats {
+					for pass in &stats.passes {
+						eprintln!(
+							"{}: {} -> {} instructions in {:?}",
+							pass.name, pass.instructions_before, pass.instructions_after, pass.duration
+						);
+					}
+				}
+				code
+			};
 
 			match args.output {
 				Output::Interpret => {
@@ -20,18 +75,76 @@ fn main() -> anyhow::Result<()> {
 					if let Some(limit) = args.instruction_limit {
 						interpreter.set_instruction_limit(limit);
 					}
-					interpreter.run(code.instructions()).context("executing")
+					#[cfg(feature = "jit")]
+					let result = interpreter.run_jit(&code);
+					#[cfg(not(feature = "jit"))]
+					let result = interpreter.run(&code);
+					result.with_context(|| {
+						match code
+							.spans()
+							.and_then(|spans| spans.get(interpreter.instruction_pointer()))
+						{
+							Some(span) => format!("executing code at {span}"),
+							None => "executing".to_owned(),
+						}
+					})
 				}
 				Output::Render => code
 					.render_c(std::io::stdout().lock())
 					.context("rendering C code"),
+				Output::Bytecode => code
+					.write_bytecode(std::io::stdout().lock())
+					.context("writing bytecode"),
+				Output::Bf => code
+					.render_bf(
+						std::io::stdout().lock(),
+						RenderBfOptions::default()
+							.line_width(args.line_width)
+							.run_length_comments(args.run_length_comments),
+					)
+					.context("rendering Brainfuck code"),
+				Output::Rust => code
+					.render_rust(std::io::stdout().lock(), RenderRustOptions::default())
+					.context("rendering Rust code"),
+				Output::Js => code
+					.render_js(std::io::stdout().lock())
+					.context("rendering JavaScript code"),
+				Output::Wasm => code
+					.write_wasm(std::io::stdout().lock())
+					.context("writing WebAssembly"),
+				Output::Asm => code
+					.render_asm(
+						std::io::stdout().lock(),
+						RenderAsmOptions::default().bounds_checks(args.bounds_checks),
+					)
+					.context("rendering assembly"),
+				Output::Elf => code
+					.write_elf(
+						std::io::stdout().lock(),
+						RenderAsmOptions::default().bounds_checks(args.bounds_checks),
+					)
+					.context("writing ELF executable"),
+				Output::Llvm => code
+					.render_llvm(std::io::stdout().lock())
+					.context("rendering LLVM IR"),
+				Output::Python => code
+					.render_python(std::io::stdout().lock())
+					.context("rendering Python code"),
 			}
 		}};
 	}
 
-	match args.mode {
+	match mode {
 		Mode::U8 => run_different_sizes!(u8),
 		Mode::U16 => run_different_sizes!(u16),
 		Mode::U32 => run_different_sizes!(u32),
 	}
 }
+
+fn compile_error(error: &bfirs::compile::Error, args: &Args) -> ! {
+	eprint!(
+		"{}",
+		diagnostic::render(error, &args.code, &args.source_name)
+	);
+	std::process::exit(1);
+}
@@ -1,20 +1,20 @@
 use std::io;
-use std::num::{NonZeroU32, NonZeroU8};
 use std::time::Instant;
 
-use bfirs::{Instruction, Interpreter};
+use bfirs::{Instruction, Interpreter, Program};
 
 type Cell = u8;
-
-const PROGRAM: &[Instruction<Cell>] = &[
-	Instruction::Inc(unsafe { NonZeroU8::new_unchecked(1) }),
-	Instruction::LoopStart(6),
-	Instruction::IncPtr(unsafe { NonZeroU32::new_unchecked(1) }),
-	Instruction::Dec(unsafe { NonZeroU8::new_unchecked(2) }),
-	Instruction::Inc(unsafe { NonZeroU8::new_unchecked(4) }),
-	Instruction::DecPtr(unsafe { NonZeroU32::new_unchecked(1) }),
-	Instruction::LoopEnd(1),
-];
+type Op = Instruction<Cell>;
+
+const PROGRAM: Program<'static, Cell> = Program::expect_valid(&[
+	Op::inc(1),
+	Op::LoopStart(6),
+	Op::inc_ptr(1),
+	Op::dec(2),
+	Op::inc(4),
+	Op::dec_ptr(1),
+	Op::LoopEnd(1),
+]);
 const ITERATIONS: u64 = 100_000;
 
 fn main() {
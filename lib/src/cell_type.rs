@@ -7,7 +7,7 @@ mod sealed {
 		+ Eq
 		+ From<u8>
 		+ Into<u32>
-		+ Into<u32>
+		+ TryFrom<u32>
 		+ Ord
 		+ std::fmt::Debug
 		+ std::fmt::Display
@@ -22,9 +22,12 @@ mod sealed {
 		const ONE_NON_ZERO: Self::NonZero;
 		const MAX: u32;
 		const C_TYPE: &'static str;
+		const BYTES: u8;
 
 		fn wrapping_add(self, amount: Self) -> Self;
 		fn wrapping_sub(self, amount: Self) -> Self;
+		fn wrapping_mul(self, factor: Self) -> Self;
+		fn wrapping_neg(self) -> Self;
 		fn checked_add(self, amount: Self) -> Option<Self>;
 		fn truncate_to_byte(self) -> u8;
 	}
@@ -40,9 +43,11 @@ macro_rules! impl_cell_type {
 
 			const ZERO: Self = 0;
 			const ONE: Self = 1;
-			const ONE_NON_ZERO: Self::NonZero = unsafe { std::num::$non_zero_ty::new_unchecked(1) };
+			const ONE_NON_ZERO: Self::NonZero = std::num::$non_zero_ty::new(1).unwrap();
 			const MAX: u32 = <$ty>::MAX as u32;
 			const C_TYPE: &'static str = $c_type;
+			#[allow(clippy::cast_possible_truncation)] // at most 4
+			const BYTES: u8 = std::mem::size_of::<$ty>() as u8;
 
 			fn wrapping_add(self, amount: Self) -> Self {
 				self.wrapping_add(amount)
@@ -52,10 +57,19 @@ macro_rules! impl_cell_type {
 				self.wrapping_sub(amount)
 			}
 
+			fn wrapping_mul(self, factor: Self) -> Self {
+				self.wrapping_mul(factor)
+			}
+
+			fn wrapping_neg(self) -> Self {
+				self.wrapping_neg()
+			}
+
 			fn checked_add(self, amount: Self) -> Option<Self> {
 				self.checked_add(amount)
 			}
 
+			#[allow(clippy::cast_possible_truncation)] // truncation is the point
 			fn truncate_to_byte(self) -> u8 {
 				self as u8
 			}
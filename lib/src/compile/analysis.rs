@@ -0,0 +1,192 @@
+//! Facts about the loops of an instruction stream.
+
+use std::collections::BTreeSet;
+
+use super::InstructionStream;
+use crate::cell_type::CellType;
+use crate::instruction::Instruction;
+
+/// Facts about a single loop, from [`InstructionStream::loops`].
+///
+/// Offsets are relative to the pointer at the start of an iteration, and facts that depend on where the pointer is are `None` when that isn't known, for example after a scan or an inner loop that moves the pointer.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LoopSummary<T: CellType> {
+	/// The index of the loop start.
+	pub start: usize,
+	/// The index of the loop end.
+	pub end: usize,
+	/// The number of loops that this loop is inside of.
+	pub depth: usize,
+	/// How far one iteration moves the pointer, if that is the same for every iteration.
+	pub pointer_delta: Option<i64>,
+	/// The offsets of the cells that one iteration accesses, in order, including those accessed by inner loops.
+	pub cells: Option<Vec<i64>>,
+	/// Whether the loop reads input or writes output, including in inner loops.
+	pub does_io: bool,
+	/// How much one iteration adds to the loop cell, wrapping, so decrementing by one is the cell type's maximum.
+	///
+	/// `None` unless the loop is balanced and only changes the loop cell by adding constants.
+	pub loop_cell_delta: Option<T>,
+}
+
+impl<T: CellType> LoopSummary<T> {
+	/// Whether every iteration ends with the pointer where it started.
+	#[must_use]
+	pub fn is_balanced(&self) -> bool {
+		self.pointer_delta == Some(0)
+	}
+}
+
+/// What is known about one iteration of a loop so far.
+struct Frame<T> {
+	start: usize,
+	pointer: Option<i64>,
+	cells: Option<BTreeSet<i64>>,
+	does_io: bool,
+	loop_cell_delta: Option<T>,
+}
+
+impl<T: CellType> Frame<T> {
+	fn new(start: usize) -> Self {
+		Self {
+			start,
+			pointer: Some(0),
+			cells: Some(BTreeSet::new()),
+			does_io: false,
+			loop_cell_delta: Some(T::ZERO),
+		}
+	}
+
+	/// The offset of the cell at `offset` from the pointer.
+	fn at(&self, offset: i32) -> Option<i64> {
+		self.pointer.map(|pointer| pointer + i64::from(offset))
+	}
+
+	fn access(&mut self, at: Option<i64>) {
+		match (&mut self.cells, at) {
+			(Some(cells), Some(at)) => {
+				cells.insert(at);
+			}
+			_ => self.cells = None,
+		}
+	}
+
+	/// Record that the cell at `at` is changed, by adding `amount` if that is known.
+	fn change(&mut self, at: Option<i64>, amount: Option<T>) {
+		if at == Some(0) {
+			self.loop_cell_delta = self
+				.loop_cell_delta
+				.zip(amount)
+				.map(|(delta, amount)| delta.wrapping_add(amount));
+		} else if at.is_none() {
+			self.loop_cell_delta = None;
+		}
+	}
+
+	fn transfer(&mut self, instruction: Instruction<T>) {
+		match instruction {
+			Instruction::IncPtr(amount) => {
+				self.pointer = self
+					.pointer
+					.map(|pointer| pointer + i64::from(amount.get()));
+			}
+			Instruction::DecPtr(amount) => {
+				self.pointer = self
+					.pointer
+					.map(|pointer| pointer - i64::from(amount.get()));
+			}
+			Instruction::ScanRight(..) | Instruction::ScanLeft(..) => {
+				self.pointer = None;
+				self.access(None);
+			}
+			Instruction::MulAdd(offset, _) => {
+				let target = self.at(offset);
+				self.access(self.pointer);
+				self.access(target);
+				self.change(target, None);
+			}
+			Instruction::WriteConst(..) => self.does_io = true,
+			Instruction::LoopStart(..) | Instruction::LoopEnd(..) => {
+				unreachable!("loops have their own frame")
+			}
+			_ => {
+				let at = self.at(instruction.cell_offset().unwrap());
+				self.access(at);
+				match instruction {
+					Instruction::Inc(amount) | Instruction::AddAt(_, amount) => {
+						self.change(at, Some(amount.into()));
+					}
+					Instruction::Dec(amount) => self.change(at, Some(amount.into().wrapping_neg())),
+					Instruction::Set(..) | Instruction::SetAt(..) => self.change(at, None),
+					Instruction::Read | Instruction::ReadAt(..) => {
+						self.does_io = true;
+						self.change(at, None);
+					}
+					Instruction::Write | Instruction::WriteAt(..) => self.does_io = true,
+					_ => unreachable!(),
+				}
+			}
+		}
+	}
+
+	/// Record the effects of an inner loop, which runs any number of times.
+	fn inner_loop(&mut self, inner: &LoopSummary<T>) {
+		self.does_io |= inner.does_io;
+		self.access(self.pointer);
+		if !inner.is_balanced() {
+			self.pointer = None;
+		}
+		if let (Some(pointer), Some(cells)) = (self.pointer, &inner.cells) {
+			for &cell in cells {
+				self.access(Some(pointer + cell));
+			}
+			// the inner loop may change any cell it accesses
+			if cells.contains(&-pointer) {
+				self.loop_cell_delta = None;
+			}
+		} else {
+			self.access(None);
+			self.loop_cell_delta = None;
+		}
+	}
+
+	fn finish(self, end: usize, depth: usize) -> LoopSummary<T> {
+		LoopSummary {
+			start: self.start,
+			end,
+			depth,
+			pointer_delta: self.pointer,
+			cells: self.cells.map(|cells| cells.into_iter().collect()),
+			does_io: self.does_io,
+			loop_cell_delta: self.loop_cell_delta.filter(|_| self.pointer == Some(0)),
+		}
+	}
+}
+
+impl<T: CellType> InstructionStream<T> {
+	/// Iterate over the loops of the stream in order of where they start, with facts about each one.
+	///
+	/// The facts hold for optimized and unoptimized streams.
+	#[allow(clippy::missing_panics_doc)] // panic is exceptional
+	pub fn loops(&self) -> impl Iterator<Item = LoopSummary<T>> {
+		let mut summaries = Vec::new();
+		// the frames of the loops that are currently open, with a frame for the code outside of loops at the bottom
+		let mut frames = vec![Frame::<T>::new(0)];
+
+		for (idx, &instruction) in self.instructions.iter().enumerate() {
+			match instruction {
+				Instruction::LoopStart(..) => frames.push(Frame::new(idx)),
+				Instruction::LoopEnd(..) => {
+					let frame = frames.pop().unwrap();
+					let summary = frame.finish(idx, frames.len() - 1);
+					frames.last_mut().unwrap().inner_loop(&summary);
+					summaries.push(summary);
+				}
+				_ => frames.last_mut().unwrap().transfer(instruction),
+			}
+		}
+
+		summaries.sort_by_key(|summary| summary.start);
+		summaries.into_iter()
+	}
+}
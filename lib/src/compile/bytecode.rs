@@ -0,0 +1,319 @@
+//! A compact binary format for saving and loading instruction streams.
+//!
+//! The format is:
+//!
+//! - the magic bytes [`MAGIC`],
+//! - the format version, one byte, currently [`VERSION`],
+//! - the cell width in bytes, one byte,
+//! - the number of instructions, as a little-endian `u32`,
+//! - the instructions, each an opcode byte followed by its fields in little-endian order, with cell values taking the cell width,
+//! - a CRC-32 of everything before it, as a little-endian `u32`.
+
+use std::io;
+use std::num::NonZeroU32;
+
+use super::{InstructionStream, PointerRange};
+use crate::cell_type::CellType;
+use crate::instruction::Instruction;
+use crate::program::Program;
+
+/// The bytes that every bytecode file starts with.
+pub const MAGIC: [u8; 4] = *b"\x7fBFC";
+
+/// The version of the format written by [`InstructionStream::write_bytecode`].
+pub const VERSION: u8 = 1;
+
+const HEADER_LEN: usize = MAGIC.len() + 2 + 4;
+const CHECKSUM_LEN: usize = 4;
+
+/// Errors that can occur while loading bytecode.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, thiserror::Error)]
+pub enum Error {
+	/// The input doesn't start with [`MAGIC`].
+	#[error("not bytecode")]
+	NotBytecode,
+	/// The input was written by an unsupported version of the format.
+	#[error("unsupported bytecode version {0}")]
+	UnsupportedVersion(u8),
+	/// The input is for a different cell width.
+	#[error("bytecode is for {found}-byte cells, not {expected}-byte cells")]
+	CellWidth {
+		/// The cell width that was being loaded.
+		expected: u8,
+		/// The cell width in the header.
+		found: u8,
+	},
+	/// The input ends before all of the instructions it declares.
+	#[error("bytecode is truncated")]
+	Truncated,
+	/// The input has bytes between its last instruction and its checksum.
+	#[error("bytecode has trailing bytes")]
+	TrailingBytes,
+	/// The checksum doesn't match the contents.
+	#[error("bytecode checksum mismatch")]
+	Checksum,
+	/// An instruction has an unknown opcode.
+	#[error("invalid opcode {opcode} for instruction {index}")]
+	InvalidOpcode {
+		/// The index of the instruction.
+		index: usize,
+		/// The opcode.
+		opcode: u8,
+	},
+	/// An instruction has a field that must be nonzero set to zero.
+	#[error("zero field in instruction {0}")]
+	ZeroField(usize),
+	/// A loop instruction jumps somewhere other than its matching loop instruction.
+	#[error("invalid jump target for instruction {0}")]
+	InvalidJump(usize),
+}
+
+/// Get the cell width of `bytecode` from its header, so it can be loaded with the right cell type.
+///
+/// # Errors
+///
+/// Returns `Err` iff `bytecode` doesn't start with a supported header.
+pub fn cell_width(bytecode: &[u8]) -> Result<u8, Error> {
+	if bytecode.len() < HEADER_LEN {
+		return Err(if bytecode.starts_with(&MAGIC) {
+			Error::Truncated
+		} else {
+			Error::NotBytecode
+		});
+	}
+	if bytecode[..MAGIC.len()] != MAGIC {
+		return Err(Error::NotBytecode);
+	}
+	match bytecode[MAGIC.len()] {
+		VERSION => Ok(bytecode[MAGIC.len() + 1]),
+		version => Err(Error::UnsupportedVersion(version)),
+	}
+}
+
+impl<T: CellType> InstructionStream<T> {
+	/// Write the instruction stream as bytecode to the writer `out`.
+	///
+	/// Spans are not written.
+	///
+	/// # Errors
+	///
+	/// Returns `Err` iff writing to `out` returns `Err`.
+	#[allow(clippy::missing_panics_doc)] // panic is exceptional
+	pub fn write_bytecode(&self, mut out: impl io::Write) -> io::Result<()> {
+		let mut bytes = Vec::with_capacity(HEADER_LEN + self.instructions.len() * 2 + CHECKSUM_LEN);
+		bytes.extend(MAGIC);
+		bytes.push(VERSION);
+		bytes.push(T::BYTES);
+		bytes.extend(
+			u32::try_from(self.instructions.len())
+				.unwrap()
+				.to_le_bytes(),
+		);
+
+		for &instruction in &self.instructions {
+			encode(instruction, &mut bytes);
+		}
+
+		let checksum = crc32(&bytes);
+		bytes.extend(checksum.to_le_bytes());
+		out.write_all(&bytes)
+	}
+
+	/// Load an instruction stream from bytecode written by [`write_bytecode`](Self::write_bytecode).
+	///
+	/// The jump targets are checked to pair up every loop start with its loop end.
+	///
+	/// # Errors
+	///
+	/// See the variants of [`Error`].
+	pub fn from_bytecode(bytecode: &[u8]) -> Result<Self, Error> {
+		let width = cell_width(bytecode)?;
+		if width != T::BYTES {
+			return Err(Error::CellWidth {
+				expected: T::BYTES,
+				found: width,
+			});
+		}
+
+		let (contents, checksum) = bytecode
+			.split_last_chunk()
+			.filter(|(contents, _)| contents.len() >= HEADER_LEN)
+			.ok_or(Error::Truncated)?;
+		if crc32(contents) != u32::from_le_bytes(*checksum) {
+			return Err(Error::Checksum);
+		}
+
+		let mut reader = Reader {
+			bytes: &contents[MAGIC.len() + 2..],
+		};
+		let len = reader.u32()? as usize;
+
+		// every instruction takes at least one byte, so don't trust `len` with a larger allocation
+		let mut instructions = Vec::with_capacity(len.min(reader.bytes.len()));
+		for index in 0..len {
+			instructions.push(decode(&mut reader, index)?);
+		}
+		if !reader.bytes.is_empty() {
+			return Err(Error::TrailingBytes);
+		}
+
+		Program::new(&instructions).map_err(|error| Error::InvalidJump(error.index()))?;
+
+		Ok(Self {
+			pointer_range: PointerRange::of(&instructions),
+			instructions,
+			spans: None,
+		})
+	}
+}
+
+fn encode<T: CellType>(instruction: Instruction<T>, out: &mut Vec<u8>) {
+	let cell =
+		|out: &mut Vec<u8>, value: T| out.extend(&value.into().to_le_bytes()[..T::BYTES.into()]);
+	let nonzero = |out: &mut Vec<u8>, value: T::NonZero| cell(out, value.into());
+
+	match instruction {
+		Instruction::Set(value) => {
+			out.push(0);
+			cell(out, value);
+		}
+		Instruction::Write => out.push(1),
+		Instruction::Read => out.push(2),
+		Instruction::LoopStart(end) => {
+			out.push(3);
+			out.extend(end.to_le_bytes());
+		}
+		Instruction::LoopEnd(start) => {
+			out.push(4);
+			out.extend(start.to_le_bytes());
+		}
+		Instruction::Inc(amount) => {
+			out.push(5);
+			nonzero(out, amount);
+		}
+		Instruction::Dec(amount) => {
+			out.push(6);
+			nonzero(out, amount);
+		}
+		Instruction::IncPtr(amount) => {
+			out.push(7);
+			out.extend(amount.get().to_le_bytes());
+		}
+		Instruction::DecPtr(amount) => {
+			out.push(8);
+			out.extend(amount.get().to_le_bytes());
+		}
+		Instruction::MulAdd(offset, factor) => {
+			out.push(9);
+			out.extend(offset.to_le_bytes());
+			nonzero(out, factor);
+		}
+		Instruction::ScanRight(stride) => {
+			out.push(10);
+			out.extend(stride.get().to_le_bytes());
+		}
+		Instruction::ScanLeft(stride) => {
+			out.push(11);
+			out.extend(stride.get().to_le_bytes());
+		}
+		Instruction::SetAt(offset, value) => {
+			out.push(12);
+			out.extend(offset.to_le_bytes());
+			cell(out, value);
+		}
+		Instruction::AddAt(offset, amount) => {
+			out.push(13);
+			out.extend(offset.to_le_bytes());
+			nonzero(out, amount);
+		}
+		Instruction::ReadAt(offset) => {
+			out.push(14);
+			out.extend(offset.to_le_bytes());
+		}
+		Instruction::WriteAt(offset) => {
+			out.push(15);
+			out.extend(offset.to_le_bytes());
+		}
+		Instruction::WriteConst(byte) => {
+			out.push(16);
+			out.push(byte);
+		}
+	}
+}
+
+fn decode<T: CellType>(reader: &mut Reader<'_>, index: usize) -> Result<Instruction<T>, Error> {
+	let nonzero = |reader: &mut Reader<'_>| {
+		T::NonZero::try_from(reader.cell::<T>()?).map_err(|_| Error::ZeroField(index))
+	};
+	let nonzero_u32 =
+		|reader: &mut Reader<'_>| NonZeroU32::new(reader.u32()?).ok_or(Error::ZeroField(index));
+
+	Ok(match reader.u8()? {
+		0 => Instruction::Set(reader.cell()?),
+		1 => Instruction::Write,
+		2 => Instruction::Read,
+		3 => Instruction::LoopStart(reader.u32()?),
+		4 => Instruction::LoopEnd(reader.u32()?),
+		5 => Instruction::Inc(nonzero(reader)?),
+		6 => Instruction::Dec(nonzero(reader)?),
+		7 => Instruction::IncPtr(nonzero_u32(reader)?),
+		8 => Instruction::DecPtr(nonzero_u32(reader)?),
+		9 => Instruction::MulAdd(reader.i32()?, nonzero(reader)?),
+		10 => Instruction::ScanRight(nonzero_u32(reader)?),
+		11 => Instruction::ScanLeft(nonzero_u32(reader)?),
+		12 => Instruction::SetAt(reader.i32()?, reader.cell()?),
+		13 => Instruction::AddAt(reader.i32()?, nonzero(reader)?),
+		14 => Instruction::ReadAt(reader.i32()?),
+		15 => Instruction::WriteAt(reader.i32()?),
+		16 => Instruction::WriteConst(reader.u8()?),
+		opcode => return Err(Error::InvalidOpcode { index, opcode }),
+	})
+}
+
+struct Reader<'b> {
+	bytes: &'b [u8],
+}
+
+impl Reader<'_> {
+	fn take<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+		let (taken, rest) = self.bytes.split_first_chunk().ok_or(Error::Truncated)?;
+		self.bytes = rest;
+		Ok(*taken)
+	}
+
+	fn u8(&mut self) -> Result<u8, Error> {
+		self.take().map(u8::from_le_bytes)
+	}
+
+	fn u32(&mut self) -> Result<u32, Error> {
+		self.take().map(u32::from_le_bytes)
+	}
+
+	fn i32(&mut self) -> Result<i32, Error> {
+		self.take().map(i32::from_le_bytes)
+	}
+
+	fn cell<T: CellType>(&mut self) -> Result<T, Error> {
+		let width = usize::from(T::BYTES);
+		if self.bytes.len() < width {
+			return Err(Error::Truncated);
+		}
+		let mut value = [0; 4];
+		value[..width].copy_from_slice(&self.bytes[..width]);
+		self.bytes = &self.bytes[width..];
+		// the value fits because it has the width of the cell
+		Ok(T::try_from(u32::from_le_bytes(value)).unwrap_or(T::ZERO))
+	}
+}
+
+/// The CRC-32 used by zlib and PNG.
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+	let mut crc = !0u32;
+	for &byte in bytes {
+		crc ^= u32::from(byte);
+		for _ in 0..8 {
+			crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+		}
+	}
+	!crc
+}
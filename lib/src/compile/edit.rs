@@ -0,0 +1,79 @@
+use std::ops::{Bound, RangeBounds};
+
+use super::{Error, InstructionStream, PointerRange};
+use crate::cell_type::CellType;
+
+impl<T: CellType> InstructionStream<T> {
+	/// Insert the instructions of `other` before the instruction at `index`.
+	///
+	/// Jump targets are updated, and since both streams have balanced loops, so does the result.
+	/// Spans are kept only if both streams have them.
+	///
+	/// # Panics
+	///
+	/// Panics if `index` is greater than the length of the stream.
+	pub fn insert(&mut self, index: usize, other: Self) {
+		self.spans = match (self.spans.take(), other.spans) {
+			(Some(mut spans), Some(other_spans)) => {
+				spans.splice(index..index, other_spans);
+				Some(spans)
+			}
+			_ => None,
+		};
+		self.instructions.splice(index..index, other.instructions);
+		self
+			.update_jump_points()
+			.expect("inserting balanced loops keeps loops balanced");
+	}
+
+	/// Insert the instructions of `other` at the end of this stream.
+	///
+	/// See [`insert`](Self::insert).
+	pub fn append(&mut self, other: Self) {
+		self.insert(self.instructions.len(), other);
+	}
+
+	/// Insert the instructions of `other` at the start of this stream.
+	///
+	/// See [`insert`](Self::insert).
+	pub fn prepend(&mut self, other: Self) {
+		self.insert(0, other);
+	}
+
+	/// Concatenate this stream with `other`, like running one after the other.
+	///
+	/// See [`insert`](Self::insert).
+	#[must_use]
+	pub fn concat(mut self, other: Self) -> Self {
+		self.append(other);
+		self
+	}
+
+	/// Remove the instructions in `range`, updating jump targets.
+	///
+	/// # Errors
+	///
+	/// Returns `Err` iff removing the instructions would leave unmatched loop starts or ends, in which case the stream is not changed.
+	///
+	/// # Panics
+	///
+	/// Panics if `range` is out of bounds.
+	pub fn remove(&mut self, range: impl RangeBounds<usize>) -> Result<(), Error> {
+		let range: (Bound<usize>, Bound<usize>) =
+			(range.start_bound().cloned(), range.end_bound().cloned());
+
+		let mut edited = Self {
+			instructions: self.instructions.clone(),
+			spans: self.spans.clone(),
+			pointer_range: PointerRange::Unbounded,
+		};
+		edited.instructions.drain(range);
+		if let Some(spans) = &mut edited.spans {
+			spans.drain(range);
+		}
+		edited.update_jump_points()?;
+
+		*self = edited;
+		Ok(())
+	}
+}
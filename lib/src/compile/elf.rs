@@ -0,0 +1,94 @@
+use std::io;
+
+use super::{x86, InstructionStream, RenderAsmOptions};
+use crate::cell_type::CellType;
+
+/// The address that the file is loaded at.
+const BASE: u64 = 0x40_0000;
+const PAGE_SIZE: u64 = 0x1000;
+
+const HEADER_LEN: u16 = 64;
+const PROGRAM_HEADER_LEN: u16 = 56;
+const PROGRAM_HEADERS: u16 = 3;
+
+const PT_LOAD: u32 = 1;
+const PT_GNU_STACK: u32 = 0x6474_e551;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+impl<T: CellType> InstructionStream<T> {
+	/// Write the instruction stream as a statically linked x86-64 Linux executable to the writer `out`.
+	///
+	/// The machine code is the same as what [`render_asm`](Self::render_asm) renders with the same options, so no assembler or linker is needed.
+	///
+	/// # Errors
+	///
+	/// Returns `Err` iff writing to `out` returns `Err`, or the data array is too large to address.
+	#[allow(clippy::missing_panics_doc)] // panic is exceptional
+	pub fn write_elf(&self, mut out: impl io::Write, options: RenderAsmOptions) -> io::Result<()> {
+		let encoded = x86::encode(&x86::linux(self, options.bounds_checks));
+		let code_start = u64::from(HEADER_LEN + PROGRAM_HEADER_LEN * PROGRAM_HEADERS);
+		let file_len = code_start + encoded.bytes.len() as u64;
+		let bss_address = (BASE + file_len).next_multiple_of(PAGE_SIZE);
+		let entry = BASE + code_start + encoded.entry.expect("program has an entry") as u64;
+		let bss_len = encoded.bss_len;
+		let code = encoded
+			.link(bss_address - BASE - code_start)
+			.ok_or_else(|| {
+				io::Error::new(
+					io::ErrorKind::InvalidInput,
+					"data array is too large to address",
+				)
+			})?;
+
+		let mut bytes = Vec::with_capacity(usize::try_from(file_len).unwrap_or(0));
+		// identification: 64-bit, little-endian, version 1, System V
+		bytes.extend(b"\x7fELF\x02\x01\x01\x00");
+		bytes.extend([0; 8]);
+		// an executable for x86-64, version 1
+		bytes.extend(2_u16.to_le_bytes());
+		bytes.extend(0x3e_u16.to_le_bytes());
+		bytes.extend(1_u32.to_le_bytes());
+		bytes.extend(entry.to_le_bytes());
+		// program headers right after this header, and no section headers
+		bytes.extend(u64::from(HEADER_LEN).to_le_bytes());
+		bytes.extend(0_u64.to_le_bytes());
+		bytes.extend(0_u32.to_le_bytes());
+		bytes.extend(HEADER_LEN.to_le_bytes());
+		bytes.extend(PROGRAM_HEADER_LEN.to_le_bytes());
+		bytes.extend(PROGRAM_HEADERS.to_le_bytes());
+		bytes.extend([0; 6]);
+
+		// the whole file, for the headers, code and read-only data
+		program_header(&mut bytes, PT_LOAD, PF_R | PF_X, BASE, file_len, file_len);
+		// the zeroed data
+		program_header(&mut bytes, PT_LOAD, PF_R | PF_W, bss_address, 0, bss_len);
+		// a stack that isn't executable
+		program_header(&mut bytes, PT_GNU_STACK, PF_R | PF_W, 0, 0, 0);
+
+		bytes.extend(code);
+		out.write_all(&bytes)
+	}
+}
+
+/// Push a program header for a segment that starts at the start of the file.
+fn program_header(
+	bytes: &mut Vec<u8>,
+	kind: u32,
+	flags: u32,
+	address: u64,
+	file_len: u64,
+	memory_len: u64,
+) {
+	bytes.extend(kind.to_le_bytes());
+	bytes.extend(flags.to_le_bytes());
+	// the offset in the file
+	bytes.extend(0_u64.to_le_bytes());
+	// the virtual and physical addresses
+	bytes.extend(address.to_le_bytes());
+	bytes.extend(address.to_le_bytes());
+	bytes.extend(file_len.to_le_bytes());
+	bytes.extend(memory_len.to_le_bytes());
+	bytes.extend(PAGE_SIZE.to_le_bytes());
+}
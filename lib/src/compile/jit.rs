@@ -0,0 +1,197 @@
+//! Compile instructions to machine code that runs in this process.
+//!
+//! The code is lowered like [`render_asm`](super::InstructionStream::render_asm) with bounds checks, but reads and writes by calling back into Rust, and returns where it stopped instead of exiting.
+
+use std::ffi::c_void;
+use std::{io, ptr};
+
+use super::x86::{self, Assembly, Inst, Label, Lower, Mem, Operand, Reg, Src, Width};
+use crate::cell_type::CellType;
+use crate::instruction::Instruction;
+
+/// Reads a byte using the context, returning it or a negative number if reading fails.
+pub(crate) type ReadFn = extern "sysv64" fn(*mut c_void) -> i32;
+/// Writes a byte using the context, returning a negative number if writing fails.
+pub(crate) type WriteFn = extern "sysv64" fn(*mut c_void, u8) -> i32;
+
+/// The code ran to the end.
+pub(crate) const STATUS_DONE: u64 = 0;
+/// The pointer left the data array.
+pub(crate) const STATUS_OUT_OF_BOUNDS: u64 = 1;
+/// Reading or writing failed.
+pub(crate) const STATUS_IO_ERROR: u64 = 2;
+
+/// Where and why compiled code stopped.
+#[repr(C)]
+#[derive(Default)]
+pub(crate) struct Stop {
+	/// One of the `STATUS_` constants.
+	pub status: u64,
+	/// The pointer, in bytes.
+	pub pointer: usize,
+	/// The index of the instruction that stopped the code, unless it ran to the end.
+	///
+	/// If the pointer left the data array, the pointer is where it was before this instruction.
+	pub index: usize,
+}
+
+const ENTRY: &str = "bf_jit";
+const EXIT: Label = Label::Named("bf_exit");
+
+/// The registers that the code uses and must preserve for its caller.
+const SAVED: [Reg; 6] = [Reg::Rbx, Reg::Rbp, Reg::R12, Reg::R13, Reg::R14, Reg::R15];
+
+/// Machine code in executable memory.
+pub(crate) struct Compiled {
+	memory: *mut c_void,
+	len: usize,
+	entry: usize,
+}
+
+impl Compiled {
+	/// Compile `instructions` to code that reads with `read` and writes with `write`.
+	///
+	/// # Errors
+	///
+	/// Returns `Err` iff the memory for the code can't be mapped.
+	pub fn new<T: CellType>(
+		instructions: &[Instruction<T>],
+		read: ReadFn,
+		write: WriteFn,
+	) -> io::Result<Self> {
+		let encoded = x86::encode(&assemble(instructions, read, write));
+		let entry = encoded.entry.expect("code has an entry");
+		let code = encoded.link(0).expect("code has no zeroed data");
+		let len = code.len();
+
+		// SAFETY: mapping new memory doesn't affect any other memory
+		let memory = unsafe {
+			libc::mmap(
+				ptr::null_mut(),
+				len,
+				libc::PROT_READ | libc::PROT_WRITE,
+				libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+				-1,
+				0,
+			)
+		};
+		if memory == libc::MAP_FAILED {
+			return Err(io::Error::last_os_error());
+		}
+		let compiled = Self { memory, len, entry };
+
+		// SAFETY: the memory was just mapped as writable, and is `len` bytes long.
+		// it's never writable and executable at the same time.
+		unsafe {
+			ptr::copy_nonoverlapping(code.as_ptr(), memory.cast(), len);
+			if libc::mprotect(memory, len, libc::PROT_READ | libc::PROT_EXEC) != 0 {
+				return Err(io::Error::last_os_error());
+			}
+		}
+		Ok(compiled)
+	}
+
+	/// Run the code on the data array at `tape`, which is `len` bytes long, starting with the pointer `pointer` bytes into it.
+	///
+	/// # Safety
+	///
+	/// `context` must be what the read and write functions expect, `tape` must be valid for `len` bytes of cells of the type the code was compiled for, and `pointer` must be a cell in it.
+	pub unsafe fn run(
+		&self,
+		context: *mut c_void,
+		tape: *mut u8,
+		pointer: usize,
+		len: usize,
+	) -> Stop {
+		// SAFETY: the entry is a function with this signature
+		let entry: extern "sysv64" fn(*mut Stop, *mut c_void, *mut u8, usize, usize) =
+			std::mem::transmute(self.memory.cast::<u8>().add(self.entry));
+		let mut stop = Stop::default();
+		entry(ptr::addr_of_mut!(stop), context, tape, pointer, len);
+		stop
+	}
+}
+
+impl Drop for Compiled {
+	fn drop(&mut self) {
+		// SAFETY: the memory was mapped with this length, and nothing refers to it anymore
+		unsafe {
+			libc::munmap(self.memory, self.len);
+		}
+	}
+}
+
+/// Assemble a function that runs `instructions`, with the signature that [`Compiled::run`] calls.
+fn assemble<T: CellType>(
+	instructions: &[Instruction<T>],
+	read: ReadFn,
+	write: WriteFn,
+) -> Assembly {
+	let lower = Lower::resumable(instructions);
+	let mov = |src, dst| Inst::Mov(Width::Quad, Src::Reg(src), Operand::Reg(dst));
+	let status = |status: u64| {
+		Inst::Mov(
+			Width::Long,
+			Src::Imm(status.try_into().unwrap()),
+			Operand::Reg(Reg::Rax),
+		)
+	};
+	let stop = |disp| {
+		Operand::Mem(Mem {
+			base: Reg::R13,
+			index: None,
+			disp,
+		})
+	};
+
+	let mut text = vec![Inst::Label(Label::Named(ENTRY))];
+	text.extend(SAVED.map(Inst::Push));
+	// the stop is at r13, the context is r12, and the cells are at r14 + rbx with r15 bytes of them
+	text.extend([
+		mov(Reg::Rdi, Reg::R13),
+		mov(Reg::Rsi, Reg::R12),
+		mov(Reg::Rdx, Reg::R14),
+		mov(Reg::Rcx, Reg::Rbx),
+		mov(Reg::R8, Reg::R15),
+	]);
+	text.extend(lower.text);
+	text.extend([status(STATUS_DONE), Inst::Jmp(EXIT)]);
+	text.extend(lower.resume);
+
+	text.extend([
+		Inst::Label(x86::OVERFLOW),
+		Inst::Label(x86::UNDERFLOW),
+		status(STATUS_OUT_OF_BOUNDS),
+		Inst::Jmp(EXIT),
+		Inst::Label(x86::IO_ERROR),
+		status(STATUS_IO_ERROR),
+		Inst::Label(EXIT),
+		Inst::Mov(Width::Quad, Src::Reg(Reg::Rax), stop(0)),
+		Inst::Mov(Width::Quad, Src::Reg(Reg::Rbx), stop(8)),
+		Inst::Mov(Width::Quad, Src::Reg(Reg::Rcx), stop(16)),
+	]);
+	text.extend(SAVED.iter().rev().copied().map(Inst::Pop));
+	text.push(Inst::Ret);
+
+	// the stack is aligned for calls in these, since the entry pushes an even number of registers
+	text.extend([
+		Inst::Label(x86::READ),
+		mov(Reg::R12, Reg::Rdi),
+		Inst::MovAbs(read as usize as u64, Reg::Rax),
+		Inst::CallReg(Reg::Rax),
+		Inst::Ret,
+		Inst::Label(x86::WRITE),
+		mov(Reg::R12, Reg::Rdi),
+		// the byte is already zero-extended
+		Inst::Mov(Width::Long, Src::Reg(Reg::Rax), Operand::Reg(Reg::Rsi)),
+		Inst::MovAbs(write as usize as u64, Reg::Rax),
+		Inst::CallReg(Reg::Rax),
+		Inst::Ret,
+	]);
+
+	Assembly {
+		text,
+		entry: Some(ENTRY),
+		..Assembly::default()
+	}
+}
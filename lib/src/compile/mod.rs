@@ -1,20 +1,65 @@
 //! Compile and optimize Brainfuck input.
 
+use std::fmt;
+
 use crate::cell_type::CellType;
 use crate::instruction::Instruction;
+use crate::span::{Position, Span};
 
+pub mod analysis;
+pub mod bytecode;
+mod edit;
+mod elf;
+#[cfg(all(feature = "jit", target_os = "linux", target_arch = "x86_64"))]
+pub(crate) mod jit;
 mod optimize;
+mod pointer_range;
+mod render_asm;
+mod render_bf;
 mod render_c;
+mod render_js;
+mod render_llvm;
+mod render_python;
+mod render_rust;
+pub mod tree;
+pub mod wasm;
+mod x86;
+
+pub(crate) use optimize::KnownCells;
+pub use optimize::{OptimizeOptions, OptimizeStats, Pass, PassStats};
+pub use pointer_range::PointerRange;
+pub use render_asm::RenderAsmOptions;
+pub use render_bf::RenderBfOptions;
+pub use render_rust::RenderRustOptions;
 
 /// Errors that can occur while compiling.
-#[derive(Copy, Clone, Debug, thiserror::Error)]
+///
+/// Positions are in the source code if the stream was created from it.
+/// Otherwise, they are as if each instruction was a single byte on one line.
+#[derive(Clone, PartialEq, Eq, Debug, thiserror::Error)]
 pub enum Error {
-	/// A loop is started but not ended.
-	#[error("unmatched loop start")]
-	UnmatchedStart,
+	/// Loops are started but not ended.
+	///
+	/// Contains the position of every unclosed loop start, in order.
+	#[error("unmatched loop start at {}", DisplayPositions(.0))]
+	UnmatchedStart(Vec<Position>),
 	/// A loop is ended without being started.
-	#[error("unmatched loop end")]
-	UnmatchedEnd,
+	#[error("unmatched loop end at {0}")]
+	UnmatchedEnd(Position),
+}
+
+struct DisplayPositions<'a>(&'a [Position]);
+
+impl fmt::Display for DisplayPositions<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		for (idx, position) in self.0.iter().enumerate() {
+			if idx > 0 {
+				f.write_str(", ")?;
+			}
+			write!(f, "{position}")?;
+		}
+		Ok(())
+	}
 }
 
 /// A stream of instructions.
@@ -23,7 +68,9 @@ pub enum Error {
 #[derive(Debug)]
 pub struct InstructionStream<T: CellType> {
 	instructions: Vec<Instruction<T>>,
-	recommended_array_size: usize,
+	/// The span of source code that each instruction came from, if tracked.
+	spans: Option<Vec<Span>>,
+	pointer_range: PointerRange,
 }
 
 impl<T: CellType> InstructionStream<T> {
@@ -36,7 +83,8 @@ impl<T: CellType> InstructionStream<T> {
 	pub fn new(instructions: Vec<Instruction<T>>) -> Result<Self, Error> {
 		let mut stream = Self {
 			instructions,
-			recommended_array_size: crate::MIN_DATA_ARRAY_SIZE,
+			spans: None,
+			pointer_range: PointerRange::Unbounded,
 		};
 		stream.update_jump_points()?;
 		Ok(stream)
@@ -49,10 +97,38 @@ impl<T: CellType> InstructionStream<T> {
 	/// Returns `Err` iff there are unmatched loop starts or ends.
 	#[allow(clippy::missing_panics_doc)] // panic is exceptional
 	pub fn from_code(input: impl Iterator<Item = u8>) -> Result<Self, Error> {
-		let instructions = Self::instructions_from_text(input);
+		let instructions = Self::instructions_from_text(input)?;
 		Self::new(instructions)
 	}
 
+	/// Create a new instruction stream from Brainfuck code, tracking the span of source code that each instruction came from.
+	///
+	/// The spans are kept up to date through optimization.
+	///
+	/// # Errors
+	///
+	/// Returns `Err` iff there are unmatched loop starts or ends.
+	pub fn from_code_with_spans(input: impl Iterator<Item = u8>) -> Result<Self, Error> {
+		let mut position = Position::START;
+		let (instructions, spans) = input
+			.filter_map(|byte| {
+				let span = Span::of_byte(position, byte);
+				position = span.end;
+				Instruction::try_from(byte)
+					.ok()
+					.map(|instruction| (instruction, span))
+			})
+			.unzip();
+
+		let mut stream = Self {
+			instructions,
+			spans: Some(spans),
+			pointer_range: PointerRange::Unbounded,
+		};
+		stream.update_jump_points()?;
+		Ok(stream)
+	}
+
 	/// Create a new instruction stream from Brainfuck code and optimize it.
 	///
 	/// This is faster than using `from_code` and then calling `optimize`.
@@ -62,8 +138,9 @@ impl<T: CellType> InstructionStream<T> {
 	/// Returns `Err` iff there are unmatched loop starts or ends.
 	pub fn optimized_from_code(input: impl Iterator<Item = u8>) -> Result<Self, Error> {
 		let mut stream = Self {
-			instructions: Self::instructions_from_text(input),
-			recommended_array_size: crate::MIN_DATA_ARRAY_SIZE,
+			instructions: Self::instructions_from_text(input)?,
+			spans: None,
+			pointer_range: PointerRange::Unbounded,
 		};
 
 		stream.optimize()?;
@@ -71,10 +148,18 @@ impl<T: CellType> InstructionStream<T> {
 		Ok(stream)
 	}
 
+	/// Returns the cells that this stream can access, relative to the initial data pointer.
+	#[must_use]
+	pub fn pointer_range(&self) -> PointerRange {
+		self.pointer_range
+	}
+
 	/// Returns a statically-guessed array size that would work best for this brainfuck stream.
+	///
+	/// See [`PointerRange::recommended_array_size`].
 	#[must_use]
 	pub fn recommended_array_size(&self) -> usize {
-		self.recommended_array_size
+		self.pointer_range.recommended_array_size()
 	}
 }
 
@@ -85,42 +170,87 @@ impl<T: CellType> InstructionStream<T> {
 		&self.instructions
 	}
 
+	/// Get the span of source code that each instruction came from.
+	///
+	/// Returns `None` unless the stream was created with [`from_code_with_spans`](Self::from_code_with_spans).
+	#[must_use]
+	pub fn spans(&self) -> Option<&[Span]> {
+		self.spans.as_deref()
+	}
+
 	/// Consume the instruction stream, returning just the instructions.
 	#[must_use]
 	pub fn into_instructions(self) -> Vec<Instruction<T>> {
 		self.instructions
 	}
 
-	#[must_use]
-	fn instructions_from_text(text: impl Iterator<Item = u8>) -> Vec<Instruction<T>> {
-		text
-			.filter_map(|byte| Instruction::try_from(byte).ok())
-			.collect()
+	/// Parse instructions from text, checking that the loops are balanced so errors can point at the source.
+	fn instructions_from_text(text: impl Iterator<Item = u8>) -> Result<Vec<Instruction<T>>, Error> {
+		let mut position = Position::START;
+		let mut starts = Vec::new();
+
+		let mut instructions = Vec::new();
+		for byte in text {
+			match byte {
+				b'[' => starts.push(position),
+				b']' => {
+					starts.pop().ok_or(Error::UnmatchedEnd(position))?;
+				}
+				_ => {}
+			}
+			position = position.advance(byte);
+
+			if let Ok(instruction) = Instruction::try_from(byte) {
+				instructions.push(instruction);
+			}
+		}
+
+		if starts.is_empty() {
+			Ok(instructions)
+		} else {
+			Err(Error::UnmatchedStart(starts))
+		}
 	}
 
+	/// The position of the instruction at `idx`, from its span if tracked.
+	fn position(&self, idx: usize) -> Position {
+		match &self.spans {
+			Some(spans) => spans[idx].start,
+			None => Position {
+				offset: idx,
+				line: 1,
+				column: idx + 1,
+			},
+		}
+	}
+
+	/// Compute the jump points, and the pointer range now that the loops are known to be balanced.
 	fn update_jump_points(&mut self) -> Result<(), Error> {
 		let mut stack = Vec::<usize>::new();
 
-		let stream = &mut self.instructions;
-
-		for idx in 0..stream.len() {
-			match stream.get(idx).unwrap() {
+		for idx in 0..self.instructions.len() {
+			match self.instructions[idx] {
 				Instruction::LoopStart(_) => {
 					stack.push(idx);
 				}
 				Instruction::LoopEnd(_) => {
-					let start_idx = stack.pop().ok_or(Error::UnmatchedEnd)?;
-					stream[start_idx] = Instruction::LoopStart(idx.try_into().unwrap());
-					stream[idx] = Instruction::LoopEnd(start_idx.try_into().unwrap());
+					let start_idx = stack
+						.pop()
+						.ok_or_else(|| Error::UnmatchedEnd(self.position(idx)))?;
+					self.instructions[start_idx] = Instruction::LoopStart(idx.try_into().unwrap());
+					self.instructions[idx] = Instruction::LoopEnd(start_idx.try_into().unwrap());
 				}
 				_ => {}
 			}
 		}
 
 		if stack.is_empty() {
+			self.pointer_range = PointerRange::of(&self.instructions);
 			Ok(())
 		} else {
-			Err(Error::UnmatchedStart)
+			Err(Error::UnmatchedStart(
+				stack.into_iter().map(|idx| self.position(idx)).collect(),
+			))
 		}
 	}
 }
@@ -0,0 +1,231 @@
+//! Constant propagation of known cell values.
+
+use std::collections::HashMap;
+
+use super::rewrite::Rewriter;
+use super::InstructionStream;
+use crate::cell_type::CellType;
+use crate::instruction::Instruction;
+
+/// What is known about the values of the cells.
+#[derive(Clone)]
+pub(crate) struct KnownCells<T> {
+	/// The value of every cell that is not in `cells`, if known.
+	rest: Option<T>,
+	/// The values of individual cells, if known.
+	///
+	/// The keys are positions relative to where the pointer was when tracking started, so moving the pointer doesn't require rekeying.
+	cells: HashMap<i64, Option<T>>,
+	/// The position of the pointer, relative to where it was when tracking started.
+	pointer: i64,
+}
+
+impl<T: CellType> KnownCells<T> {
+	pub(crate) fn all(value: Option<T>) -> Self {
+		Self {
+			rest: value,
+			cells: HashMap::new(),
+			pointer: 0,
+		}
+	}
+
+	pub(crate) fn get(&self, offset: i64) -> Option<T> {
+		self
+			.cells
+			.get(&(self.pointer + offset))
+			.copied()
+			.unwrap_or(self.rest)
+	}
+
+	pub(crate) fn set(&mut self, offset: i64, value: Option<T>) {
+		self.cells.insert(self.pointer + offset, value);
+	}
+
+	/// The offsets from the pointer of the individual cells that are known, with their values.
+	///
+	/// If `rest` is known, every other cell is too.
+	pub(crate) fn known_offsets(&self) -> impl Iterator<Item = (i64, T)> + '_ {
+		self
+			.cells
+			.iter()
+			.filter_map(|(&key, &value)| Some((key - self.pointer, value?)))
+	}
+
+	fn move_pointer(&mut self, by: i64) {
+		self.pointer += by;
+	}
+
+	/// Keep only the facts that hold in both `self` and `other`.
+	///
+	/// Both must be relative to the same pointer position.
+	pub(crate) fn join(&self, other: &Self) -> Self {
+		let offsets = self
+			.cells
+			.keys()
+			.map(|key| key - self.pointer)
+			.chain(other.cells.keys().map(|key| key - other.pointer));
+
+		let mut joined = Self::all(if self.rest == other.rest {
+			self.rest
+		} else {
+			None
+		});
+		for offset in offsets {
+			let value = self.get(offset);
+			joined.set(
+				offset,
+				if value == other.get(offset) {
+					value
+				} else {
+					None
+				},
+			);
+		}
+		joined
+	}
+
+	/// Update what is known after running `instruction`, which must not be a loop instruction.
+	///
+	/// Returns the instruction to run instead, or `None` if it can be removed.
+	pub(crate) fn transfer(&mut self, instruction: Instruction<T>) -> Option<Instruction<T>> {
+		match instruction {
+			Instruction::IncPtr(amount) => {
+				self.move_pointer(i64::from(amount.get()));
+				Some(instruction)
+			}
+			Instruction::DecPtr(amount) => {
+				self.move_pointer(-i64::from(amount.get()));
+				Some(instruction)
+			}
+			Instruction::ScanRight(..) | Instruction::ScanLeft(..) => {
+				if self.get(0) == Some(T::ZERO) {
+					None
+				} else {
+					// the pointer ends up somewhere unknown
+					*self = Self::all(None);
+					self.set(0, Some(T::ZERO));
+					Some(instruction)
+				}
+			}
+			Instruction::MulAdd(offset, factor) => {
+				let offset = i64::from(offset);
+				match self.get(0) {
+					Some(value) if value == T::ZERO => None,
+					Some(value) => {
+						let product = value.wrapping_mul(factor.into());
+						match (self.get(offset), T::NonZero::try_from(product)) {
+							(_, Err(..)) => None,
+							(Some(target), Ok(..)) => {
+								let new = target.wrapping_add(product);
+								self.set(offset, Some(new));
+								Some(Instruction::SetAt(offset.try_into().unwrap(), new))
+							}
+							(None, Ok(product)) => Some(Instruction::AddAt(offset.try_into().unwrap(), product)),
+						}
+					}
+					None => {
+						self.set(offset, None);
+						Some(instruction)
+					}
+				}
+			}
+			Instruction::Set(..)
+			| Instruction::SetAt(..)
+			| Instruction::Inc(..)
+			| Instruction::Dec(..)
+			| Instruction::AddAt(..) => {
+				let offset = instruction.cell_offset().unwrap();
+				let current = self.get(offset.into());
+				let new = match instruction {
+					Instruction::Set(value) | Instruction::SetAt(_, value) => Some(value),
+					Instruction::Inc(amount) | Instruction::AddAt(_, amount) => {
+						current.map(|current| current.wrapping_add(amount.into()))
+					}
+					Instruction::Dec(amount) => current.map(|current| current.wrapping_sub(amount.into())),
+					_ => unreachable!(),
+				};
+				self.set(offset.into(), new);
+				match new {
+					Some(new) if current == Some(new) => None,
+					Some(new) => Some(Instruction::Set(new).with_cell_offset(offset)),
+					None => Some(instruction),
+				}
+			}
+			Instruction::Read | Instruction::ReadAt(..) => {
+				self.set(instruction.cell_offset().unwrap().into(), None);
+				Some(instruction)
+			}
+			Instruction::Write | Instruction::WriteAt(..) | Instruction::WriteConst(..) => {
+				Some(instruction)
+			}
+			Instruction::LoopStart(..) | Instruction::LoopEnd(..) => unreachable!("loop instruction"),
+		}
+	}
+}
+
+impl<T: CellType> InstructionStream<T> {
+	/// Tracks the values of cells that are known at compile time, starting from a zeroed data array.
+	///
+	/// Arithmetic on known cells becomes `Set`s, `Set`s that don't change the cell are removed, and loops that are reached with a zero cell are removed entirely.
+	pub(super) fn propagate_constants(&mut self) {
+		let mut rewriter = Rewriter::new(self);
+
+		let mut known = KnownCells::all(Some(T::ZERO));
+		// for each loop that is currently open, what is known if it is skipped, or `None` if it is always entered
+		let mut skipped_states = Vec::<Option<KnownCells<T>>>::new();
+
+		while let Some(&instruction) = rewriter.remaining().first() {
+			let replacement = match instruction {
+				Instruction::LoopStart(..) => match (known.get(0), loop_end(rewriter.remaining(), 1)) {
+					(Some(value), Some(end)) if value == T::ZERO => {
+						rewriter.remove(end + 1);
+						continue;
+					}
+					(value, _) => {
+						skipped_states.push(value.is_none().then(|| {
+							let mut skipped = known.clone();
+							skipped.set(0, Some(T::ZERO));
+							skipped
+						}));
+						// the body may run any number of times, so nothing is known at its start
+						known = KnownCells::all(None);
+						Some(instruction)
+					}
+				},
+				Instruction::LoopEnd(..) => {
+					known.set(0, Some(T::ZERO));
+					// an unmatched end is reported when updating jump points
+					if let Some(Some(skipped)) = skipped_states.pop() {
+						known = known.join(&skipped);
+					}
+					Some(instruction)
+				}
+				_ => known.transfer(instruction),
+			};
+
+			match replacement {
+				Some(replacement) => rewriter.replace(1, [replacement]),
+				None => rewriter.remove(1),
+			}
+		}
+
+		rewriter.finish();
+	}
+}
+
+/// Find the index of the `LoopEnd` matching the `LoopStart` just before `body_start`.
+///
+/// Jump points are not up to date while optimizing, so this counts nesting instead.
+/// Returns `None` if the loop is unmatched.
+fn loop_end<T: CellType>(stream: &[Instruction<T>], body_start: usize) -> Option<usize> {
+	let mut depth = 0usize;
+	for (idx, instruction) in stream.iter().enumerate().skip(body_start) {
+		match instruction {
+			Instruction::LoopStart(..) => depth += 1,
+			Instruction::LoopEnd(..) if depth == 0 => return Some(idx),
+			Instruction::LoopEnd(..) => depth -= 1,
+			_ => {}
+		}
+	}
+	None
+}
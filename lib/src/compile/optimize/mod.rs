@@ -0,0 +1,434 @@
+use std::num::NonZeroU32;
+
+use rewrite::{merge_spans, Rewriter};
+
+use super::{Error, InstructionStream};
+use crate::cell_type::CellType;
+use crate::instruction::Instruction;
+use crate::span::Span;
+
+mod constants;
+mod pipeline;
+#[cfg(feature = "limited")]
+mod prefix;
+mod rewrite;
+
+pub(crate) use constants::KnownCells;
+pub use pipeline::{OptimizeOptions, OptimizeStats, Pass, PassStats};
+
+enum FoldResult<T> {
+	CantFold,
+	NoOp,
+	Folded(T),
+}
+
+impl<T: CellType> FoldResult<Instruction<T>> {
+	fn try_from_or<A, B: TryFrom<A>>(
+		or: Self,
+		value: A,
+		variant: impl FnOnce(B) -> Instruction<T>,
+	) -> Self {
+		B::try_from(value)
+			.ok()
+			.map(variant)
+			.map_or(or, Self::Folded)
+	}
+
+	fn try_from_or_noop<A, B: TryFrom<A>>(
+		value: A,
+		variant: impl FnOnce(B) -> Instruction<T>,
+	) -> Self {
+		Self::try_from_or(Self::NoOp, value, variant)
+	}
+}
+
+impl<T: CellType> Instruction<T> {
+	fn fold_with(self, next: Self) -> FoldResult<Self> {
+		match (self, next) {
+			(Self::Inc(amount1), Self::Inc(amount2)) => {
+				FoldResult::try_from_or_noop(amount1.into().wrapping_add(amount2.into()), Self::Inc)
+			}
+			(Self::Dec(amount1), Self::Dec(amount2)) => {
+				FoldResult::try_from_or_noop(amount1.into().wrapping_add(amount2.into()), Self::Dec)
+			}
+			(Self::Dec(sub), Self::Inc(add)) | (Self::Inc(add), Self::Dec(sub)) => {
+				let add = add.into();
+				let sub = sub.into();
+				if sub > add {
+					FoldResult::try_from_or_noop(sub - add, Self::Dec)
+				} else {
+					FoldResult::try_from_or_noop(add - sub, Self::Inc)
+				}
+			}
+			(Self::IncPtr(amount1), Self::IncPtr(amount2)) => amount1
+				.get()
+				.checked_add(amount2.get())
+				.and_then(NonZeroU32::new)
+				.map(Self::IncPtr)
+				.map_or(FoldResult::CantFold, FoldResult::Folded),
+			(Self::DecPtr(amount1), Self::DecPtr(amount2)) => amount1
+				.get()
+				.checked_add(amount2.get())
+				.and_then(NonZeroU32::new)
+				.map(Self::DecPtr)
+				.map_or(FoldResult::CantFold, FoldResult::Folded),
+			(Self::DecPtr(sub), Self::IncPtr(add)) | (Self::IncPtr(add), Self::DecPtr(sub)) => {
+				if sub > add {
+					FoldResult::try_from_or_noop(sub.get() - add.get(), Self::DecPtr)
+				} else {
+					FoldResult::try_from_or_noop(add.get() - sub.get(), Self::IncPtr)
+				}
+			}
+			(Self::Inc(..) | Self::Dec(..) | Self::Set(..), set @ Self::Set(..)) => {
+				FoldResult::Folded(set)
+			}
+			(Self::Set(start), Self::Inc(add)) => {
+				FoldResult::Folded(Self::Set(start.wrapping_add(add.into())))
+			}
+			(Self::Set(start), Self::Dec(sub)) => {
+				FoldResult::Folded(Self::Set(start.wrapping_sub(sub.into())))
+			}
+			(Self::AddAt(offset1, amount1), Self::AddAt(offset2, amount2)) if offset1 == offset2 => {
+				FoldResult::try_from_or_noop(amount1.into().wrapping_add(amount2.into()), |amount| {
+					Self::AddAt(offset1, amount)
+				})
+			}
+			(Self::AddAt(offset1, ..) | Self::SetAt(offset1, ..), set @ Self::SetAt(offset2, ..))
+				if offset1 == offset2 =>
+			{
+				FoldResult::Folded(set)
+			}
+			(Self::SetAt(offset1, start), Self::AddAt(offset2, add)) if offset1 == offset2 => {
+				FoldResult::Folded(Self::SetAt(offset1, start.wrapping_add(add.into())))
+			}
+			_ => FoldResult::CantFold,
+		}
+	}
+
+	/// The offset from the pointer of the single cell that this instruction accesses.
+	///
+	/// Returns `None` for instructions that move the pointer or access more than one cell.
+	pub(super) fn cell_offset(self) -> Option<i32> {
+		match self {
+			Self::Set(..) | Self::Inc(..) | Self::Dec(..) | Self::Read | Self::Write => Some(0),
+			Self::SetAt(offset, ..)
+			| Self::AddAt(offset, ..)
+			| Self::ReadAt(offset)
+			| Self::WriteAt(offset) => Some(offset),
+			_ => None,
+		}
+	}
+
+	/// Change the cell accessed by a single-cell instruction to the one at `offset`.
+	///
+	/// The plain variants are used for an offset of zero and the `*At` variants otherwise.
+	fn with_cell_offset(self, offset: i32) -> Self {
+		let amount = match self {
+			Self::Set(value) | Self::SetAt(_, value) => {
+				return if offset == 0 {
+					Self::Set(value)
+				} else {
+					Self::SetAt(offset, value)
+				};
+			}
+			Self::Read | Self::ReadAt(..) => {
+				return if offset == 0 {
+					Self::Read
+				} else {
+					Self::ReadAt(offset)
+				};
+			}
+			Self::Write | Self::WriteAt(..) => {
+				return if offset == 0 {
+					Self::Write
+				} else {
+					Self::WriteAt(offset)
+				};
+			}
+			Self::Inc(amount) | Self::AddAt(_, amount) => amount.into(),
+			Self::Dec(amount) => amount.into().wrapping_neg(),
+			_ => unreachable!("not a single-cell instruction"),
+		};
+
+		// the negation of a non-zero value is non-zero
+		let non_zero = |value: T| T::NonZero::try_from(value).ok().unwrap();
+		if offset != 0 {
+			Self::AddAt(offset, non_zero(amount))
+		} else if amount.into() > T::MAX / 2 {
+			Self::Dec(non_zero(amount.wrapping_neg()))
+		} else {
+			Self::Inc(non_zero(amount))
+		}
+	}
+}
+
+impl<T: CellType> InstructionStream<T> {
+	// without this inline attr it fails to inline this function into the main loop, preventing a considerable speedup
+	#[inline]
+	fn fold_like(&mut self) {
+		let mut rewriter = Rewriter::new(self);
+
+		'stream: while let Some((&first, rest)) = rewriter.remaining().split_first() {
+			let mut current = first;
+			let mut count = 1;
+			'fold: for &next in rest {
+				match current.fold_with(next) {
+					FoldResult::Folded(folded) => {
+						current = folded;
+						count += 1;
+					}
+					FoldResult::NoOp => {
+						rewriter.remove(count + 1); // we did read the next instruction
+						continue 'stream;
+					}
+					FoldResult::CantFold => {
+						break 'fold;
+					}
+				}
+			}
+			rewriter.replace(count, [current]);
+		}
+
+		rewriter.finish();
+	}
+
+	/// Replaces multiplication loops, like `[->+>++<<]`, with `MulAdd`s followed by a `Set(0)`.
+	///
+	/// Zeroing loops (`[-]` and `[+]`) are the degenerate case with no `MulAdd`s.
+	fn recognize_multiplications(&mut self) {
+		let mut rewriter = Rewriter::new(self);
+		let mut replacement = Vec::new();
+
+		while let Some(instruction) = rewriter.remaining().first() {
+			if let Instruction::LoopStart(..) = instruction {
+				if let Some(body_len) = multiplication_loop(&rewriter.remaining()[1..], &mut replacement) {
+					rewriter.replace(body_len + 2, replacement.iter().copied());
+					continue;
+				}
+			}
+			rewriter.keep(1);
+		}
+
+		rewriter.finish();
+	}
+
+	/// Replaces scan loops, like `[>]` and `[<<]`, with `ScanRight`s and `ScanLeft`s.
+	fn recognize_scans(&mut self) {
+		let mut rewriter = Rewriter::new(self);
+
+		while !rewriter.remaining().is_empty() {
+			let scan = match *rewriter.remaining() {
+				[Instruction::LoopStart(..), Instruction::IncPtr(stride), Instruction::LoopEnd(..), ..] => {
+					Instruction::ScanRight(stride)
+				}
+				[Instruction::LoopStart(..), Instruction::DecPtr(stride), Instruction::LoopEnd(..), ..] => {
+					Instruction::ScanLeft(stride)
+				}
+				_ => {
+					rewriter.keep(1);
+					continue;
+				}
+			};
+			rewriter.replace(3, [scan]);
+		}
+
+		rewriter.finish();
+	}
+
+	/// Rewrites each run of straight-line instructions into instructions that access cells at offsets from the pointer, followed by a single pointer movement.
+	///
+	/// For example, `>+>++<<-` becomes `AddAt(1, 1)`, `AddAt(2, 2)`, `Dec(1)`.
+	fn canonicalize_blocks(&mut self) {
+		let mut rewriter = Rewriter::new(self);
+		let mut block = Vec::new();
+
+		while !rewriter.remaining().is_empty() {
+			let block_len = rewriter
+				.remaining()
+				.iter()
+				.take_while(|instruction| {
+					instruction.cell_offset().is_some()
+						|| matches!(
+							instruction,
+							Instruction::IncPtr(..) | Instruction::DecPtr(..) | Instruction::WriteConst(..)
+						)
+				})
+				.count();
+
+			if block_len == 0 {
+				rewriter.keep(1);
+			} else if canonicalize_block(
+				&rewriter.remaining()[..block_len],
+				rewriter.remaining_spans().map(|spans| &spans[..block_len]),
+				&mut block,
+			) {
+				// each instruction produces at most one instruction, and the final pointer movement is only needed if the block contained one
+				rewriter.replace_with_spans(block_len, block.iter().copied());
+			} else {
+				rewriter.keep(block_len);
+			}
+		}
+
+		rewriter.finish();
+	}
+}
+
+/// Rewrites a block of straight-line instructions and their spans into `out`, as described in `canonicalize_blocks`.
+///
+/// Returns `false` if an offset in the block does not fit.
+fn canonicalize_block<T: CellType>(
+	block: &[Instruction<T>],
+	spans: Option<&[Span]>,
+	out: &mut Vec<(Instruction<T>, Option<Span>)>,
+) -> bool {
+	out.clear();
+	let mut position = 0i64;
+	let mut movement_span = None;
+
+	for (idx, &instruction) in block.iter().enumerate() {
+		let span = spans.map(|spans| spans[idx]);
+		match instruction {
+			Instruction::IncPtr(amount) => {
+				position += i64::from(amount.get());
+				movement_span = merge_spans(movement_span.or(span), span);
+			}
+			Instruction::DecPtr(amount) => {
+				position -= i64::from(amount.get());
+				movement_span = merge_spans(movement_span.or(span), span);
+			}
+			// doesn't access any cell, so it never needs to be folded
+			Instruction::WriteConst(..) => out.push((instruction, span)),
+			_ => {
+				let offset = position + i64::from(instruction.cell_offset().unwrap());
+				let Ok(offset) = i32::try_from(offset) else {
+					return false;
+				};
+				push_folded(out, instruction.with_cell_offset(offset), span);
+			}
+		}
+	}
+
+	let Ok(distance) = u32::try_from(position.unsigned_abs()) else {
+		return false;
+	};
+	if let Some(distance) = NonZeroU32::new(distance) {
+		let movement = if position > 0 {
+			Instruction::IncPtr(distance)
+		} else {
+			Instruction::DecPtr(distance)
+		};
+		out.push((movement, movement_span));
+	}
+
+	true
+}
+
+/// Pushes the single-cell instruction `instruction` to `block`, folding it with the last instruction that accessed the same cell if possible.
+///
+/// Cell arithmetic on different cells commutes, so the folded instruction can be moved back past any instructions that don't access its cell.
+fn push_folded<T: CellType>(
+	block: &mut Vec<(Instruction<T>, Option<Span>)>,
+	instruction: Instruction<T>,
+	span: Option<Span>,
+) {
+	let offset = instruction.cell_offset();
+	if let Some(last_idx) = block
+		.iter()
+		.rposition(|(other, _)| other.cell_offset() == offset)
+	{
+		let (last, last_span) = block[last_idx];
+		match last.fold_with(instruction) {
+			FoldResult::Folded(folded) => {
+				block[last_idx] = (folded, merge_spans(last_span, span));
+				return;
+			}
+			FoldResult::NoOp => {
+				block.remove(last_idx);
+				return;
+			}
+			FoldResult::CantFold => {}
+		}
+	}
+	block.push((instruction, span));
+}
+
+/// Checks whether `body`, which starts right after a `LoopStart`, is the body of a multiplication loop.
+///
+/// That is, a loop with only cell and pointer arithmetic, that returns the pointer to where it started, and that increments or decrements the loop cell by exactly one.
+///
+/// If so, the equivalent instructions are written to `out` and the length of the body (not including the `LoopEnd`) is returned.
+fn multiplication_loop<T: CellType>(
+	body: &[Instruction<T>],
+	out: &mut Vec<Instruction<T>>,
+) -> Option<usize> {
+	let mut offset = 0i64;
+	// kept in order of first modification so the output is deterministic
+	let mut deltas = Vec::<(i64, T)>::new();
+
+	for (idx, &instruction) in body.iter().enumerate() {
+		let (at, change) = match instruction {
+			Instruction::Inc(amount) => (offset, amount.into()),
+			Instruction::Dec(amount) => (offset, amount.into().wrapping_neg()),
+			Instruction::AddAt(at, amount) => (offset + i64::from(at), amount.into()),
+			Instruction::IncPtr(amount) => {
+				offset += i64::from(amount.get());
+				continue;
+			}
+			Instruction::DecPtr(amount) => {
+				offset -= i64::from(amount.get());
+				continue;
+			}
+			Instruction::LoopEnd(..) => {
+				return write_multiplication(offset, &deltas, out).then_some(idx);
+			}
+			_ => return None,
+		};
+
+		match deltas.iter_mut().find(|(other, _)| *other == at) {
+			Some((_, delta)) => *delta = delta.wrapping_add(change),
+			None => deltas.push((at, change)),
+		}
+	}
+
+	None
+}
+
+fn write_multiplication<T: CellType>(
+	final_offset: i64,
+	deltas: &[(i64, T)],
+	out: &mut Vec<Instruction<T>>,
+) -> bool {
+	if final_offset != 0 {
+		return false;
+	}
+
+	let counter = deltas
+		.iter()
+		.find(|(offset, _)| *offset == 0)
+		.map_or(T::ZERO, |&(_, delta)| delta);
+	// a loop that increments its counter runs `-counter` times rather than `counter` times
+	let negate = if counter == T::ONE.wrapping_neg() {
+		false
+	} else if counter == T::ONE {
+		true
+	} else {
+		return false;
+	};
+
+	out.clear();
+	for &(offset, delta) in deltas {
+		if offset == 0 {
+			continue;
+		}
+		let Ok(offset) = i32::try_from(offset) else {
+			return false;
+		};
+		let factor = if negate { delta.wrapping_neg() } else { delta };
+		if let Ok(factor) = T::NonZero::try_from(factor) {
+			out.push(Instruction::MulAdd(offset, factor));
+		}
+	}
+	out.push(Instruction::Set(T::ZERO));
+
+	true
+}
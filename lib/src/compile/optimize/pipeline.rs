@@ -0,0 +1,331 @@
+//! Choosing which optimization passes run, and collecting statistics about them.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use super::{Error, InstructionStream};
+use crate::cell_type::CellType;
+use crate::instruction::Instruction;
+use crate::span::Span;
+
+/// A user-supplied optimization pass.
+///
+/// Custom passes run after the built-in passes, in the order they were added to [`OptimizeOptions`].
+pub trait Pass<T: CellType> {
+	/// The name of the pass, as reported in [`PassStats`].
+	fn name(&self) -> &str;
+
+	/// Run the pass on the instructions.
+	///
+	/// Jump points do not need to be kept up to date, as they are recomputed after all passes have run, but loops must stay balanced.
+	///
+	/// If the stream tracks spans, `spans` has the span of each instruction, and must be kept the same length as `instructions`.
+	///
+	/// To work on loops structurally, convert the instructions with [`tree::build`](crate::compile::tree::build) and back with [`tree::flatten`](crate::compile::tree::flatten).
+	fn run(&mut self, instructions: &mut Vec<Instruction<T>>, spans: Option<&mut Vec<Span>>);
+}
+
+/// Options for [`InstructionStream::optimize_with`].
+///
+/// The default is optimization level 2.
+#[allow(clippy::struct_excessive_bools)] // each one toggles a pass
+pub struct OptimizeOptions<T: CellType> {
+	fold: bool,
+	multiplications: bool,
+	scans: bool,
+	offsets: bool,
+	constants: bool,
+	#[cfg(feature = "limited")]
+	prefix_step_budget: Option<u64>,
+	passes: Vec<Box<dyn Pass<T>>>,
+}
+
+impl<T: CellType> fmt::Debug for OptimizeOptions<T> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let mut debug = f.debug_struct("OptimizeOptions");
+		debug
+			.field("fold", &self.fold)
+			.field("multiplications", &self.multiplications)
+			.field("scans", &self.scans)
+			.field("offsets", &self.offsets)
+			.field("constants", &self.constants);
+		#[cfg(feature = "limited")]
+		debug.field("prefix_step_budget", &self.prefix_step_budget);
+		debug
+			.field(
+				"passes",
+				&self
+					.passes
+					.iter()
+					.map(|pass| pass.name())
+					.collect::<Vec<_>>(),
+			)
+			.finish()
+	}
+}
+
+impl<T: CellType> Default for OptimizeOptions<T> {
+	fn default() -> Self {
+		Self::level(2)
+	}
+}
+
+impl<T: CellType> OptimizeOptions<T> {
+	/// The step budget used for prefix evaluation at optimization level 3.
+	#[cfg(feature = "limited")]
+	pub const DEFAULT_PREFIX_STEP_BUDGET: u64 = 10_000_000;
+
+	/// Create options with no passes enabled.
+	///
+	/// The jump points and recommended array size are still computed.
+	#[must_use]
+	pub fn none() -> Self {
+		Self {
+			fold: false,
+			multiplications: false,
+			scans: false,
+			offsets: false,
+			constants: false,
+			#[cfg(feature = "limited")]
+			prefix_step_budget: None,
+			passes: Vec::new(),
+		}
+	}
+
+	/// Create options for an optimization level.
+	///
+	/// - Level 0 enables no passes.
+	/// - Level 1 enables folding and multiplication and scan loop recognition.
+	/// - Level 2 also enables offset addressing.
+	/// - Level 3 and above also enable constant propagation, and prefix evaluation with [`DEFAULT_PREFIX_STEP_BUDGET`](Self::DEFAULT_PREFIX_STEP_BUDGET) if the `limited` feature is enabled.
+	///
+	/// Up to level 2, the optimized stream doesn't depend on what the data array is filled with.
+	/// Level 3 assumes that it is zeroed.
+	#[must_use]
+	pub fn level(level: u8) -> Self {
+		let options = Self::none()
+			.fold(level >= 1)
+			.multiplications(level >= 1)
+			.scans(level >= 1)
+			.offsets(level >= 2)
+			.constants(level >= 3);
+		#[cfg(feature = "limited")]
+		let options = options.prefix_evaluation((level >= 3).then_some(Self::DEFAULT_PREFIX_STEP_BUDGET));
+		options
+	}
+
+	/// Enable or disable folding runs of similar instructions, like `+++` or `><`.
+	#[must_use]
+	pub fn fold(self, enabled: bool) -> Self {
+		Self {
+			fold: enabled,
+			..self
+		}
+	}
+
+	/// Enable or disable replacing multiplication loops, like `[->++<]`, with `MulAdd`s.
+	#[must_use]
+	pub fn multiplications(self, enabled: bool) -> Self {
+		Self {
+			multiplications: enabled,
+			..self
+		}
+	}
+
+	/// Enable or disable replacing scan loops, like `[>]`, with `ScanRight`s and `ScanLeft`s.
+	#[must_use]
+	pub fn scans(self, enabled: bool) -> Self {
+		Self {
+			scans: enabled,
+			..self
+		}
+	}
+
+	/// Enable or disable rewriting straight-line code to access cells at offsets from the pointer.
+	#[must_use]
+	pub fn offsets(self, enabled: bool) -> Self {
+		Self {
+			offsets: enabled,
+			..self
+		}
+	}
+
+	/// Enable or disable propagating known cell values.
+	///
+	/// This assumes that the data array is zeroed when the stream starts running.
+	#[must_use]
+	pub fn constants(self, enabled: bool) -> Self {
+		Self {
+			constants: enabled,
+			..self
+		}
+	}
+
+	/// Enable prefix evaluation with the given step budget, or disable it with `None`.
+	///
+	/// See [`InstructionStream::optimize_with`] for what this assumes.
+	#[cfg(feature = "limited")]
+	#[must_use]
+	pub fn prefix_evaluation(self, step_budget: Option<u64>) -> Self {
+		Self {
+			prefix_step_budget: step_budget,
+			..self
+		}
+	}
+
+	/// Add a custom pass.
+	#[must_use]
+	pub fn pass(mut self, pass: impl Pass<T> + 'static) -> Self {
+		self.passes.push(Box::new(pass));
+		self
+	}
+}
+
+/// Statistics about a single run of an optimization pass.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PassStats {
+	/// The name of the pass.
+	pub name: String,
+	/// The number of instructions before the pass ran.
+	pub instructions_before: usize,
+	/// The number of instructions after the pass ran.
+	pub instructions_after: usize,
+	/// How long the pass took.
+	pub duration: Duration,
+}
+
+impl PassStats {
+	/// The number of instructions removed by the pass.
+	///
+	/// Passes like prefix evaluation can add instructions, in which case this is `0`.
+	#[must_use]
+	pub fn instructions_removed(&self) -> usize {
+		self
+			.instructions_before
+			.saturating_sub(self.instructions_after)
+	}
+}
+
+/// Statistics about an optimization run, returned by [`InstructionStream::optimize_with`].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct OptimizeStats {
+	/// Every pass that ran, in order.
+	///
+	/// Some passes run more than once.
+	pub passes: Vec<PassStats>,
+}
+
+impl OptimizeStats {
+	/// The total number of instructions removed by the pass named `name`, over all of its runs.
+	#[must_use]
+	pub fn instructions_removed_by(&self, name: &str) -> usize {
+		self
+			.passes
+			.iter()
+			.filter(|pass| pass.name == name)
+			.map(PassStats::instructions_removed)
+			.sum()
+	}
+}
+
+impl<T: CellType> InstructionStream<T> {
+	/// Optimize the instruction stream with the default options.
+	///
+	/// # Errors
+	///
+	/// Will return `Err` if there are unmatched loop starts or ends.
+	pub fn optimize(&mut self) -> Result<(), Error> {
+		self.optimize_with(&mut OptimizeOptions::default())?;
+		Ok(())
+	}
+
+	/// Optimize the instruction stream with the passes enabled in `options`, returning statistics about each pass.
+	///
+	/// With constant propagation, the optimized stream assumes that the data array is zeroed when it starts running, which is the case unless [`Builder::fill`](crate::interpret::Builder::fill) is used.
+	/// With prefix evaluation, the part of the stream before the first `Read` is run at compile time, within the step budget, and replaced with instructions that recreate its results.
+	/// This also assumes that there are at least [`MIN_DATA_ARRAY_SIZE`](crate::MIN_DATA_ARRAY_SIZE) cells starting at the initial data pointer.
+	///
+	/// # Errors
+	///
+	/// Will return `Err` if there are unmatched loop starts or ends.
+	#[allow(clippy::missing_panics_doc)] // panic is exceptional
+	pub fn optimize_with(
+		&mut self,
+		options: &mut OptimizeOptions<T>,
+	) -> Result<OptimizeStats, Error> {
+		let mut stats = OptimizeStats::default();
+
+		self.run_builtin_passes(options, &mut stats);
+
+		#[cfg(feature = "limited")]
+		if let Some(step_budget) = options.prefix_step_budget {
+			self.update_jump_points()?;
+			self.run_pass(&mut stats, "prefix", |stream| {
+				stream.evaluate_prefix(step_budget);
+			});
+			// propagate the values that the evaluated part left behind
+			self.run_builtin_passes(options, &mut stats);
+		}
+
+		for pass in &mut options.passes {
+			let name = pass.name().to_owned();
+			self.run_pass(&mut stats, &name, |stream| {
+				pass.run(&mut stream.instructions, stream.spans.as_mut());
+			});
+			if let Some(spans) = &self.spans {
+				assert_eq!(
+					spans.len(),
+					self.instructions.len(),
+					"pass {name:?} did not keep the spans in sync with the instructions"
+				);
+			}
+		}
+
+		self.update_jump_points()?;
+
+		Ok(stats)
+	}
+
+	fn run_builtin_passes(&mut self, options: &OptimizeOptions<T>, stats: &mut OptimizeStats) {
+		if options.fold {
+			self.run_pass(stats, "fold", Self::fold_like);
+		}
+		if options.multiplications {
+			self.run_pass(stats, "multiplications", Self::recognize_multiplications);
+		}
+		if options.scans {
+			self.run_pass(stats, "scans", Self::recognize_scans);
+		}
+		if options.fold {
+			self.run_pass(stats, "fold", Self::fold_like);
+		}
+		if options.offsets {
+			self.run_pass(stats, "offsets", Self::canonicalize_blocks);
+		}
+		if options.constants {
+			// removing loops and `Set`s can bring blocks together, which can make more `Set`s redundant
+			loop {
+				let len = self.instructions.len();
+				self.run_pass(stats, "constants", Self::propagate_constants);
+				if options.offsets {
+					self.run_pass(stats, "offsets", Self::canonicalize_blocks);
+				}
+				if self.instructions.len() == len {
+					break;
+				}
+			}
+		}
+	}
+
+	fn run_pass(&mut self, stats: &mut OptimizeStats, name: &str, pass: impl FnOnce(&mut Self)) {
+		let instructions_before = self.instructions.len();
+		let start = Instant::now();
+		pass(self);
+		stats.passes.push(PassStats {
+			name: name.to_owned(),
+			instructions_before,
+			instructions_after: self.instructions.len(),
+			duration: start.elapsed(),
+		});
+	}
+}
@@ -0,0 +1,120 @@
+//! Compile-time evaluation of the part of the program that doesn't depend on input.
+
+use std::io;
+use std::num::NonZeroU32;
+
+use super::InstructionStream;
+use crate::cell_type::CellType;
+use crate::instruction::Instruction;
+use crate::interpret::Interpreter;
+use crate::program::Program;
+use crate::span::Span;
+
+/// The state of the interpreter after running part of the program.
+struct Evaluated<T> {
+	data: Box<[T]>,
+	data_pointer: usize,
+	output: Vec<u8>,
+}
+
+impl<T: CellType> InstructionStream<T> {
+	/// Replaces the longest part of the stream before the first `Read` that can be evaluated within `step_budget` instructions.
+	///
+	/// Requires up-to-date jump points, and leaves them outdated.
+	pub(super) fn evaluate_prefix(&mut self, step_budget: u64) {
+		let first_read = self
+			.instructions
+			.iter()
+			.position(|instruction| matches!(instruction, Instruction::Read | Instruction::ReadAt(..)))
+			.unwrap_or(self.instructions.len());
+
+		// the evaluated part has to end between top-level instructions so the rest of the stream is still balanced
+		let mut depth = 0usize;
+		let mut prefix_end = 0;
+		for (idx, instruction) in self.instructions[..first_read].iter().enumerate() {
+			match instruction {
+				Instruction::LoopStart(..) => depth += 1,
+				Instruction::LoopEnd(..) => depth -= 1,
+				_ => {}
+			}
+			if depth == 0 {
+				prefix_end = idx + 1;
+			}
+		}
+
+		let prefix = Program::new(&self.instructions[..prefix_end]).expect("prefix ends between loops");
+		let (end, evaluated) = evaluate(prefix, step_budget);
+		if end == 0 {
+			return;
+		}
+
+		let mut replacement: Vec<_> = evaluated
+			.output
+			.into_iter()
+			.map(Instruction::WriteConst)
+			.collect();
+		for (offset, &cell) in evaluated.data.iter().enumerate() {
+			if cell != T::ZERO {
+				replacement.push(Instruction::Set(cell).with_cell_offset(offset.try_into().unwrap()));
+			}
+		}
+		if let Some(distance) = NonZeroU32::new(evaluated.data_pointer.try_into().unwrap()) {
+			replacement.push(Instruction::IncPtr(distance));
+		}
+
+		if let Some(spans) = &mut self.spans {
+			let span = spans[..end].iter().copied().reduce(Span::merge).unwrap();
+			spans.splice(..end, std::iter::repeat_n(span, replacement.len()));
+		}
+		self.instructions.splice(..end, replacement);
+	}
+}
+
+/// Run `program` one top-level instruction or loop at a time, until it finishes or one of them errors or runs out of the `step_budget`.
+///
+/// Returns where the part that succeeded ends, and the state after running it.
+fn evaluate<T: CellType>(program: Program<'_, T>, step_budget: u64) -> (usize, Evaluated<T>) {
+	let instructions = program.instructions();
+	let mut output = Vec::new();
+	let mut interpreter = Interpreter::build::<T, _, _>(io::empty(), &mut output)
+		.instruction_limit(step_budget)
+		.build();
+
+	let mut end = 0;
+	let mut failed_loop = false;
+	while end < instructions.len() {
+		let next = match instructions[end] {
+			Instruction::LoopStart(loop_end) => loop_end as usize + 1,
+			_ => end + 1,
+		};
+		if interpreter.run_range(program, end..next).is_err() {
+			failed_loop = next > end + 1;
+			break;
+		}
+		end = next;
+	}
+
+	// a single instruction errors before changing anything, but a loop can leave cells and output half-updated.
+	// the part before it already ran within the budget, so running it again does too.
+	if failed_loop {
+		drop(interpreter);
+		output.clear();
+		interpreter = Interpreter::build::<T, _, _>(io::empty(), &mut output)
+			.instruction_limit(step_budget)
+			.build();
+		interpreter
+			.run_range(program, 0..end)
+			.expect("part that already succeeded");
+	}
+
+	let data_pointer = interpreter.data_pointer();
+	let data = interpreter.into_data();
+	(
+		end,
+		Evaluated {
+			data,
+			data_pointer,
+			output,
+		},
+	)
+}
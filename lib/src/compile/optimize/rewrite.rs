@@ -0,0 +1,113 @@
+//! In-place rewriting of instruction streams that keeps the span table in sync.
+
+use super::InstructionStream;
+use crate::cell_type::CellType;
+use crate::instruction::Instruction;
+use crate::span::Span;
+
+/// Rewrites the instructions of a stream from front to back, without allocating.
+///
+/// Every pass reads the remaining instructions and decides what to do with some number of them at the front.
+/// Replacements can't be longer than what they replace, so the rewritten instructions never overwrite unread ones.
+pub(super) struct Rewriter<'s, T: CellType> {
+	instructions: &'s mut Vec<Instruction<T>>,
+	spans: Option<&'s mut Vec<Span>>,
+	read_idx: usize,
+	write_idx: usize,
+}
+
+impl<'s, T: CellType> Rewriter<'s, T> {
+	pub(super) fn new(stream: &'s mut InstructionStream<T>) -> Self {
+		Self {
+			instructions: &mut stream.instructions,
+			spans: stream.spans.as_mut(),
+			read_idx: 0,
+			write_idx: 0,
+		}
+	}
+
+	/// The instructions that have not been rewritten yet.
+	pub(super) fn remaining(&self) -> &[Instruction<T>] {
+		&self.instructions[self.read_idx..]
+	}
+
+	/// The spans of the instructions that have not been rewritten yet, if spans are tracked.
+	pub(super) fn remaining_spans(&self) -> Option<&[Span]> {
+		self.spans.as_deref().map(|spans| &spans[self.read_idx..])
+	}
+
+	/// The combined span of the next `count` instructions, if spans are tracked.
+	pub(super) fn span(&self, count: usize) -> Option<Span> {
+		self.remaining_spans()?[..count]
+			.iter()
+			.copied()
+			.reduce(Span::merge)
+	}
+
+	/// Keep the next `count` instructions as they are.
+	pub(super) fn keep(&mut self, count: usize) {
+		let range = self.read_idx..self.read_idx + count;
+		self.instructions.copy_within(range.clone(), self.write_idx);
+		if let Some(spans) = &mut self.spans {
+			spans.copy_within(range, self.write_idx);
+		}
+		self.read_idx += count;
+		self.write_idx += count;
+	}
+
+	/// Remove the next `count` instructions.
+	pub(super) fn remove(&mut self, count: usize) {
+		self.read_idx += count;
+	}
+
+	/// Replace the next `count` instructions with `replacement`, which all get the combined span of the replaced instructions.
+	pub(super) fn replace(
+		&mut self,
+		count: usize,
+		replacement: impl IntoIterator<Item = Instruction<T>>,
+	) {
+		let span = self.span(count);
+		self.replace_with_spans(
+			count,
+			replacement
+				.into_iter()
+				.map(|instruction| (instruction, span)),
+		);
+	}
+
+	/// Replace the next `count` instructions with `replacement`, which have their own spans.
+	///
+	/// The spans must be `Some` iff spans are tracked.
+	pub(super) fn replace_with_spans(
+		&mut self,
+		count: usize,
+		replacement: impl IntoIterator<Item = (Instruction<T>, Option<Span>)>,
+	) {
+		self.read_idx += count;
+		for (instruction, span) in replacement {
+			assert!(
+				self.write_idx < self.read_idx,
+				"replacement is longer than the instructions it replaces"
+			);
+			self.instructions[self.write_idx] = instruction;
+			if let (Some(spans), Some(span)) = (&mut self.spans, span) {
+				spans[self.write_idx] = span;
+			}
+			self.write_idx += 1;
+		}
+	}
+
+	/// Finish rewriting, dropping the leftover space.
+	pub(super) fn finish(self) {
+		debug_assert_eq!(self.read_idx, self.instructions.len());
+		self.instructions.truncate(self.write_idx);
+		if let Some(spans) = self.spans {
+			spans.truncate(self.write_idx);
+		}
+	}
+}
+
+/// Combine two optional spans, as tracked by the rewriter.
+pub(super) fn merge_spans(a: Option<Span>, b: Option<Span>) -> Option<Span> {
+	a.zip(b).map(|(a, b)| a.merge(b))
+}
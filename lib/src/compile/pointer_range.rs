@@ -0,0 +1,90 @@
+//! Static analysis of how far the data pointer can move.
+
+use crate::cell_type::CellType;
+use crate::instruction::Instruction;
+
+/// The cells that a stream can access, relative to the initial data pointer.
+///
+/// This includes every cell the pointer can move to, even if the cell isn't read or written there.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum PointerRange {
+	/// Only cells in `min..=max` can be accessed.
+	///
+	/// This is exact for code whose loops all leave the pointer where they found it, except that loops which are never entered are counted as if they were.
+	/// `min` is negative iff the stream can underflow the data array.
+	Bounded {
+		/// The offset of the leftmost cell that can be accessed, which is at most `0`.
+		min: i64,
+		/// The offset of the rightmost cell that can be accessed, which is at least `0`.
+		max: i64,
+	},
+	/// The pointer can move arbitrarily far, because a loop or a scan moves it.
+	Unbounded,
+}
+
+impl PointerRange {
+	/// Find the cells that `instructions` can access.
+	///
+	/// The jump points in `instructions` don't need to be up to date, but the loops must be balanced.
+	#[must_use]
+	pub fn of<T: CellType>(instructions: &[Instruction<T>]) -> Self {
+		let mut pointer = 0i64;
+		let mut min = 0i64;
+		let mut max = 0i64;
+		// the pointer at the start of each loop that is open
+		let mut loop_starts = Vec::new();
+
+		for instruction in instructions {
+			let accessed = match *instruction {
+				Instruction::IncPtr(amount) => {
+					pointer += i64::from(amount.get());
+					pointer
+				}
+				Instruction::DecPtr(amount) => {
+					pointer -= i64::from(amount.get());
+					pointer
+				}
+				Instruction::LoopStart(..) => {
+					loop_starts.push(pointer);
+					pointer
+				}
+				Instruction::LoopEnd(..) => {
+					// a loop that moves the pointer can walk it any distance
+					if loop_starts.pop() != Some(pointer) {
+						return Self::Unbounded;
+					}
+					pointer
+				}
+				Instruction::ScanRight(..) | Instruction::ScanLeft(..) => return Self::Unbounded,
+				Instruction::MulAdd(offset, _)
+				| Instruction::SetAt(offset, _)
+				| Instruction::AddAt(offset, _)
+				| Instruction::ReadAt(offset)
+				| Instruction::WriteAt(offset) => pointer + i64::from(offset),
+				Instruction::Set(..)
+				| Instruction::Write
+				| Instruction::Read
+				| Instruction::Inc(..)
+				| Instruction::Dec(..)
+				| Instruction::WriteConst(..) => pointer,
+			};
+			min = min.min(accessed);
+			max = max.max(accessed);
+		}
+
+		Self::Bounded { min, max }
+	}
+
+	/// The data array size that should be used for a stream with this range.
+	///
+	/// This is enough for every cell that can be accessed to the right of the initial data pointer, and at least [`MIN_DATA_ARRAY_SIZE`](crate::MIN_DATA_ARRAY_SIZE).
+	#[must_use]
+	pub fn recommended_array_size(self) -> usize {
+		match self {
+			Self::Bounded { max, .. } => usize::try_from(max)
+				.map_or(usize::MAX, |max| max.saturating_add(1))
+				.max(crate::MIN_DATA_ARRAY_SIZE),
+			Self::Unbounded => crate::MIN_DATA_ARRAY_SIZE,
+		}
+	}
+}
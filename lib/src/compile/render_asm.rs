@@ -0,0 +1,44 @@
+use std::io;
+
+use super::{x86, InstructionStream};
+use crate::cell_type::CellType;
+
+/// Options for [`InstructionStream::render_asm`].
+///
+/// The default doesn't check bounds.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct RenderAsmOptions {
+	pub(super) bounds_checks: bool,
+}
+
+impl RenderAsmOptions {
+	/// The exit status when reading or writing fails.
+	pub const IO_ERROR_STATUS: u8 = 1;
+	/// The exit status when the pointer moves past the end of the data array, if bounds are checked.
+	pub const OVERFLOW_STATUS: u8 = 2;
+	/// The exit status when the pointer moves before the start of the data array, if bounds are checked.
+	pub const UNDERFLOW_STATUS: u8 = 3;
+
+	/// Enable or disable checking that the pointer stays in the data array, exiting with [`OVERFLOW_STATUS`](Self::OVERFLOW_STATUS) or [`UNDERFLOW_STATUS`](Self::UNDERFLOW_STATUS) if it doesn't.
+	#[must_use]
+	pub fn bounds_checks(self, enabled: bool) -> Self {
+		Self {
+			bounds_checks: enabled,
+		}
+	}
+}
+
+impl<T: CellType> InstructionStream<T> {
+	/// Renders this instruction stream as x86-64 Linux assembly in GAS syntax to the writer `out`.
+	///
+	/// The result doesn't need libc, so it can be built with `as prog.s -o prog.o && ld prog.o -o prog`.
+	/// It makes `read`, `write` and `exit` syscalls, and reading past the end of the input reads zero.
+	/// Errors are printed to stderr with the messages of [`interpret::Error`](crate::interpret::Error), and exit with the statuses in [`RenderAsmOptions`].
+	///
+	/// # Errors
+	///
+	/// Returns `Err` iff writing to `out` returns `Err`.
+	pub fn render_asm(&self, mut out: impl io::Write, options: RenderAsmOptions) -> io::Result<()> {
+		write!(out, "{}", x86::linux(self, options.bounds_checks))
+	}
+}
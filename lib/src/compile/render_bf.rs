@@ -0,0 +1,446 @@
+use std::io;
+
+use super::optimize::KnownCells;
+use super::InstructionStream;
+use crate::cell_type::CellType;
+use crate::instruction::Instruction;
+
+/// Options for [`InstructionStream::render_bf`].
+///
+/// The default wraps lines at 80 characters and emits no comments.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RenderBfOptions {
+	line_width: Option<usize>,
+	run_length_comments: bool,
+}
+
+impl Default for RenderBfOptions {
+	fn default() -> Self {
+		Self {
+			line_width: Some(80),
+			run_length_comments: false,
+		}
+	}
+}
+
+impl RenderBfOptions {
+	/// Runs of the same character at least this long get a run-length comment, if enabled.
+	pub const RUN_LENGTH_COMMENT_MIN: u64 = 4;
+
+	/// Wrap lines at `width` characters, or never with `None`.
+	#[must_use]
+	pub fn line_width(self, width: Option<usize>) -> Self {
+		Self {
+			line_width: width,
+			..self
+		}
+	}
+
+	/// Enable or disable following long runs of the same character with their length, like `++++++6`.
+	///
+	/// See [`RUN_LENGTH_COMMENT_MIN`](Self::RUN_LENGTH_COMMENT_MIN).
+	#[must_use]
+	pub fn run_length_comments(self, enabled: bool) -> Self {
+		Self {
+			run_length_comments: enabled,
+			..self
+		}
+	}
+}
+
+impl<T: CellType> InstructionStream<T> {
+	/// Renders this instruction stream as Brainfuck code to the writer `out`.
+	///
+	/// Instructions without a direct equivalent are lowered to plain Brainfuck that uses only the cells they access, except for `WriteConst`, which temporarily changes the nearest cell with a value known at that point.
+	/// That cell is not changed back if the stream ends right after writing.
+	/// A run of `MulAdd`s that isn't followed by a `Set` similarly keeps the current cell by moving it through a nearby cell with a known value.
+	/// Large amounts are added with multiplication loops in nearby cells that are known to be zero, which are left zeroed.
+	/// Like constant propagation, this assumes that the data array is zeroed when the stream starts running.
+	///
+	/// # Errors
+	///
+	/// Returns `Err` iff writing to `out` returns `Err`, or the stream can't be lowered because no cell value is known at a `WriteConst`, or at a run of `MulAdd`s that isn't followed by a `Set`.
+	#[allow(clippy::missing_panics_doc)] // panic is exceptional
+	pub fn render_bf(&self, mut out: impl io::Write, options: RenderBfOptions) -> io::Result<()> {
+		let mut renderer = Renderer {
+			text: Vec::new(),
+			options,
+			column: 0,
+			displacement: 0,
+			borrowed: None,
+		};
+
+		let mut known = KnownCells::all(Some(T::ZERO));
+		// for each loop that is currently open, what is known if it is skipped, or `None` if it is always entered
+		let mut skipped_states = Vec::new();
+
+		let mut idx = 0;
+		while let Some(&instruction) = self.instructions.get(idx) {
+			idx += 1;
+			if !matches!(instruction, Instruction::WriteConst(..)) {
+				renderer.give_back(&known);
+			}
+			match instruction {
+				Instruction::LoopStart(..) => {
+					let always_entered = known.get(0).is_some_and(|value| value != T::ZERO);
+					skipped_states.push((!always_entered).then(|| known.clone()));
+					renderer.settle();
+					renderer.run(b'[', 1);
+					known = KnownCells::all(None);
+				}
+				Instruction::LoopEnd(..) => {
+					renderer.settle();
+					renderer.run(b']', 1);
+					known.set(0, Some(T::ZERO));
+					if let Some(Some(mut skipped)) = skipped_states.pop() {
+						skipped.set(0, Some(T::ZERO));
+						known = known.join(&skipped);
+					}
+				}
+				Instruction::MulAdd(..) => {
+					let len = self.instructions[idx - 1..]
+						.iter()
+						.take_while(|instruction| matches!(instruction, Instruction::MulAdd(..)))
+						.count();
+					// a following `Set` overwrites the current cell anyway, like in the runs that the optimizer emits
+					let keep = !matches!(
+						self.instructions.get(idx - 1 + len),
+						Some(Instruction::Set(..))
+					);
+					renderer.mul_adds(&self.instructions[idx - 1..idx - 1 + len], &mut known, keep)?;
+					idx += len - 1;
+				}
+				Instruction::ScanRight(stride) | Instruction::ScanLeft(stride) => {
+					let direction = if matches!(instruction, Instruction::ScanRight(..)) {
+						b'>'
+					} else {
+						b'<'
+					};
+					renderer.settle();
+					renderer.run(b'[', 1);
+					renderer.run(direction, stride.get().into());
+					renderer.run(b']', 1);
+					known.transfer(instruction);
+				}
+				Instruction::IncPtr(amount) => {
+					renderer.displacement -= i64::from(amount.get());
+					known.transfer(instruction);
+				}
+				Instruction::DecPtr(amount) => {
+					renderer.displacement += i64::from(amount.get());
+					known.transfer(instruction);
+				}
+				Instruction::WriteConst(byte) => {
+					renderer.write_const(byte, &known)?;
+				}
+				_ => {
+					let offset = instruction.cell_offset().unwrap();
+					renderer.move_to(offset.into());
+					match instruction {
+						Instruction::Set(value) | Instruction::SetAt(_, value) => {
+							let current = known.get(offset.into()).unwrap_or_else(|| {
+								renderer.literal(b"[-]");
+								T::ZERO
+							});
+							renderer.add(value.wrapping_sub(current), &known, &[]);
+						}
+						Instruction::Inc(amount) | Instruction::AddAt(_, amount) => {
+							renderer.add(amount.into(), &known, &[]);
+						}
+						Instruction::Dec(amount) => {
+							renderer.add(T::ZERO.wrapping_sub(amount.into()), &known, &[]);
+						}
+						Instruction::Read | Instruction::ReadAt(..) => renderer.run(b',', 1),
+						Instruction::Write | Instruction::WriteAt(..) => renderer.run(b'.', 1),
+						_ => unreachable!(),
+					}
+					known.transfer(instruction);
+				}
+			}
+		}
+
+		renderer.text.push(b'\n');
+		out.write_all(&renderer.text)
+	}
+}
+
+/// Builds the rendered text, keeping track of where the pointer really is.
+struct Renderer<T> {
+	text: Vec<u8>,
+	options: RenderBfOptions,
+	column: usize,
+	/// How far the pointer in the rendered code is from the pointer in the stream.
+	///
+	/// Moves are only rendered when a cell is accessed, so moving to and from offsets doesn't render moves that cancel out.
+	displacement: i64,
+	/// The offset, original value and current value of the cell that is being used to write constants.
+	///
+	/// Runs of `WriteConst`s keep using it, and it's given back before anything else is rendered.
+	borrowed: Option<(i64, T, T)>,
+}
+
+impl<T: CellType> Renderer<T> {
+	/// Render a run of `MulAdd`s as a single loop, which zeroes the current cell unless `keep` is set.
+	///
+	/// To keep the current cell, the loop also adds it to a spare cell with a known value, and it's moved back from there afterwards.
+	fn mul_adds(
+		&mut self,
+		run: &[Instruction<T>],
+		known: &mut KnownCells<T>,
+		keep: bool,
+	) -> io::Result<()> {
+		// the cells that change while the loop runs can't be used as scratch cells
+		let mut changed: Vec<i64> = run
+			.iter()
+			.filter_map(|instruction| match instruction {
+				Instruction::MulAdd(offset, ..) => Some(i64::from(*offset)),
+				_ => None,
+			})
+			.chain([0])
+			.collect();
+		let spare = if keep {
+			let spare = (1..=MAX_SCRATCH_DISTANCE)
+				.chain(known.known_offsets().map(|(offset, _)| offset))
+				.filter(|offset| !changed.contains(offset))
+				.filter_map(|offset| Some((offset, known.get(offset)?)))
+				.min_by_key(|&(offset, value)| (value != T::ZERO, offset.unsigned_abs()));
+			let Some(spare) = spare else {
+				return Err(io::Error::new(
+					io::ErrorKind::InvalidInput,
+					"no cell value is known at a MulAdd that isn't followed by Set",
+				));
+			};
+			changed.push(spare.0);
+			Some(spare)
+		} else {
+			None
+		};
+
+		self.settle();
+		self.run(b'[', 1);
+		self.add(T::ZERO.wrapping_sub(T::ONE), known, &changed);
+		if let Some((offset, _)) = spare {
+			self.move_to(offset);
+			self.add(T::ONE, known, &changed);
+		}
+		for &instruction in run {
+			let Instruction::MulAdd(offset, factor) = instruction else {
+				unreachable!("not a MulAdd")
+			};
+			self.move_to(offset.into());
+			self.add(factor.into(), known, &changed);
+		}
+		self.settle();
+		self.run(b']', 1);
+		if let Some((offset, value)) = spare {
+			self.move_to(offset);
+			self.add(T::ZERO.wrapping_sub(value), known, &changed);
+			self.run(b'[', 1);
+			self.add(T::ZERO.wrapping_sub(T::ONE), known, &changed);
+			self.settle();
+			self.add(T::ONE, known, &changed);
+			self.move_to(offset);
+			self.run(b']', 1);
+			self.add(value, known, &changed);
+		}
+
+		for &instruction in run {
+			known.transfer(instruction);
+		}
+		if !keep {
+			known.set(0, Some(T::ZERO));
+		}
+		Ok(())
+	}
+
+	/// Render writing `byte` by temporarily changing the nearest cell with a known value.
+	fn write_const(&mut self, byte: u8, known: &KnownCells<T>) -> io::Result<()> {
+		if let Some((offset, original, current)) = self.borrowed {
+			self.move_to(offset);
+			self.add(T::from(byte).wrapping_sub(current), known, &[]);
+			self.run(b'.', 1);
+			self.borrowed = Some((offset, original, T::from(byte)));
+			return Ok(());
+		}
+
+		let nearest = known
+			.get(self.displacement)
+			.map(|value| (self.displacement, value))
+			.or_else(|| {
+				known
+					.known_offsets()
+					.min_by_key(|&(offset, _)| (offset - self.displacement).unsigned_abs())
+			});
+		let Some((offset, value)) = nearest else {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"no cell value is known at WriteConst",
+			));
+		};
+		self.move_to(offset);
+		self.add(T::from(byte).wrapping_sub(value), known, &[]);
+		self.run(b'.', 1);
+		self.borrowed = Some((offset, value, T::from(byte)));
+		Ok(())
+	}
+
+	/// Render restoring the cell used to write constants, if any.
+	fn give_back(&mut self, known: &KnownCells<T>) {
+		if let Some((offset, original, current)) = self.borrowed.take() {
+			self.move_to(offset);
+			self.add(original.wrapping_sub(current), known, &[]);
+		}
+	}
+
+	/// Render `count` copies of `byte`.
+	fn run(&mut self, byte: u8, count: u64) {
+		for _ in 0..count {
+			self.wrap(1);
+			self.text.push(byte);
+			self.column += 1;
+		}
+		if self.options.run_length_comments && count >= RenderBfOptions::RUN_LENGTH_COMMENT_MIN {
+			self.literal(count.to_string().as_bytes());
+		}
+	}
+
+	/// Render `text`, keeping it on one line.
+	fn literal(&mut self, text: &[u8]) {
+		self.wrap(text.len());
+		self.text.extend(text);
+		self.column += text.len();
+	}
+
+	/// Start a new line if `len` more characters don't fit on this one.
+	fn wrap(&mut self, len: usize) {
+		if let Some(width) = self.options.line_width {
+			if self.column > 0 && self.column + len > width {
+				self.text.push(b'\n');
+				self.column = 0;
+			}
+		}
+	}
+
+	/// Render adding `amount` to the current cell, subtracting instead if that's shorter.
+	///
+	/// Large amounts are built up with multiplication loops in cells to the right that `known` says are zero, except for those at the offsets in `avoid`.
+	fn add(&mut self, amount: T, known: &KnownCells<T>, avoid: &[i64]) {
+		let amount = u64::from(amount.into());
+		let negated = (u64::from(T::MAX) + 1 - amount) % (u64::from(T::MAX) + 1);
+		let (byte, count) = if amount <= negated {
+			(b'+', amount)
+		} else {
+			(b'-', negated)
+		};
+
+		let target = self.displacement;
+		let scratch: Vec<i64> = (target + 1..=target + MAX_SCRATCH_DISTANCE)
+			.filter(|offset| !avoid.contains(offset) && known.get(*offset) == Some(T::ZERO))
+			.take(2)
+			.collect();
+		match build(count, byte, target, &scratch) {
+			Some(steps) if cost(&steps, target) < count => {
+				for step in steps {
+					match step {
+						Step::MoveTo(offset) => self.move_to(offset),
+						Step::Run(byte, count) => self.run(byte, count),
+					}
+				}
+			}
+			_ => self.run(byte, count),
+		}
+	}
+
+	/// Render moving the pointer to the cell at `offset` from the pointer in the stream.
+	fn move_to(&mut self, offset: i64) {
+		let distance = offset - self.displacement;
+		if distance > 0 {
+			self.run(b'>', distance.unsigned_abs());
+		} else {
+			self.run(b'<', distance.unsigned_abs());
+		}
+		self.displacement = offset;
+	}
+
+	/// Render moving the pointer to where it is in the stream.
+	fn settle(&mut self) {
+		self.move_to(0);
+	}
+}
+
+/// How far to the right of a cell to look for scratch cells when adding a large amount to it.
+const MAX_SCRATCH_DISTANCE: i64 = 8;
+
+/// A step in building up a large amount.
+#[derive(Clone, Copy)]
+enum Step {
+	MoveTo(i64),
+	Run(u8, u64),
+}
+
+/// The number of characters that `steps` render to, starting at `from`.
+fn cost(steps: &[Step], mut from: i64) -> u64 {
+	steps
+		.iter()
+		.map(|step| match *step {
+			Step::MoveTo(offset) => (offset - std::mem::replace(&mut from, offset)).unsigned_abs(),
+			Step::Run(_, count) => count,
+		})
+		.sum()
+}
+
+/// The shortest way to run `byte` (`+` or `-`) `count` times on the cell at `target` with multiplication loops, using the zeroed cells at the offsets in `scratch` and leaving them zeroed.
+///
+/// `count` is written in some base, and built up digit by digit like Horner's method, multiplying by the base with a loop each time.
+/// The most significant digit is built first in a scratch cell, and the last loop adds to the target, so its current value doesn't matter.
+/// With one scratch cell there are only two digits, and with two they take turns holding the amount so far.
+/// Returns `None` if there are no scratch cells.
+fn build(count: u64, byte: u8, target: i64, scratch: &[i64]) -> Option<Vec<Step>> {
+	let &first = scratch.first()?;
+	// large bases only make sense for two digits, which are shortest around the square root, so powers of two get close enough
+	let bases = (2..=64)
+		.chain((7..=16).map(|bits| 1 << bits))
+		.filter(|&base| base <= count);
+
+	let candidates = bases.map(|base| {
+		let mut digits = vec![count % base];
+		let mut rest = count / base;
+		while rest >= base && scratch.len() > 1 {
+			digits.push(rest % base);
+			rest /= base;
+		}
+		digits.push(rest);
+		digits.reverse();
+
+		let mut steps = vec![Step::MoveTo(first), Step::Run(b'+', digits[0])];
+		let mut current = first;
+		let (&last, middle) = digits[1..].split_last().unwrap();
+		for &digit in middle {
+			let other = if current == first { scratch[1] } else { first };
+			steps.extend([
+				Step::Run(b'[', 1),
+				Step::Run(b'-', 1),
+				Step::MoveTo(other),
+				Step::Run(b'+', base),
+				Step::MoveTo(current),
+				Step::Run(b']', 1),
+				Step::MoveTo(other),
+				Step::Run(b'+', digit),
+			]);
+			current = other;
+		}
+		steps.extend([
+			Step::Run(b'[', 1),
+			Step::Run(b'-', 1),
+			Step::MoveTo(target),
+			Step::Run(byte, base),
+			Step::MoveTo(current),
+			Step::Run(b']', 1),
+			Step::MoveTo(target),
+			Step::Run(byte, last),
+		]);
+
+		steps
+	});
+	candidates.min_by_key(|steps| cost(steps, target))
+}
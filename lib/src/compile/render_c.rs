@@ -1,6 +1,6 @@
 use std::io;
 
-use super::InstructionStream;
+use super::{InstructionStream, PointerRange};
 use crate::cell_type::CellType;
 
 impl<T: CellType> InstructionStream<T> {
@@ -11,12 +11,17 @@ impl<T: CellType> InstructionStream<T> {
 	/// Returns `Err` iff writing to `out` returns `Err`.
 	pub fn render_c(&self, mut out: impl io::Write) -> io::Result<()> {
 		let c_type = T::C_TYPE;
-		let arr_size = self.recommended_array_size;
+		// there are no bounds checks, so make room for cells left of the start rather than reading outside of the array
+		let start = match self.pointer_range() {
+			PointerRange::Bounded { min, .. } => min.unsigned_abs(),
+			PointerRange::Unbounded => 0,
+		};
+		let arr_size = self.recommended_array_size() as u64 + start;
 		writeln!(out, "#include <stdio.h>")?;
 		writeln!(out, "typedef {c_type} bf_cell_t;")?;
 		writeln!(out, "static bf_cell_t arr[{arr_size}] = {{0,}};")?;
 		writeln!(out, "int main() {{")?;
-		writeln!(out, "\tbf_cell_t* cursor = arr;")?;
+		writeln!(out, "\tbf_cell_t* cursor = arr + {start};")?;
 
 		for instruction in &self.instructions {
 			use crate::instruction::Instruction as I;
@@ -36,6 +41,21 @@ impl<T: CellType> InstructionStream<T> {
 				I::Dec(amount) => writeln!(out, "*cursor -= {amount};"),
 				I::IncPtr(amount) => writeln!(out, "cursor += {amount};"),
 				I::DecPtr(amount) => writeln!(out, "cursor -= {amount};"),
+				// the casts avoid signed overflow from integer promotion
+				I::MulAdd(offset, factor) => writeln!(
+					out,
+					"cursor[{offset}] += (bf_cell_t)((unsigned int)*cursor * {factor}u);"
+				),
+				I::ScanRight(stride) => writeln!(out, "while (*cursor != 0) {{ cursor += {stride}; }}"),
+				I::ScanLeft(stride) => writeln!(out, "while (*cursor != 0) {{ cursor -= {stride}; }}"),
+				I::SetAt(offset, value) => writeln!(out, "cursor[{offset}] = {value};"),
+				I::AddAt(offset, amount) => writeln!(out, "cursor[{offset}] += {amount};"),
+				I::ReadAt(offset) => writeln!(
+					out,
+					"cursor[{offset}] = getchar(); if (cursor[{offset}] == EOF) {{ cursor[{offset}] = 0; }}"
+				),
+				I::WriteAt(offset) => writeln!(out, "putchar(cursor[{offset}]);"),
+				I::WriteConst(byte) => writeln!(out, "putchar({byte});"),
 			}?;
 		}
 
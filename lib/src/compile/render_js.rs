@@ -0,0 +1,130 @@
+use std::io;
+
+use super::InstructionStream;
+use crate::cell_type::CellType;
+use crate::instruction::Instruction;
+
+impl<T: CellType> InstructionStream<T> {
+	/// Renders this instruction stream as a JavaScript module to the writer `out`.
+	///
+	/// The module exports a `run` function that takes the input as a `Uint8Array` and returns the output as one.
+	/// Reading past the end of the input reads zero.
+	/// The data array is a typed array with the width of the cell type and the [recommended size](Self::recommended_array_size), and every access is checked just like when interpreting.
+	/// Moving the pointer out of the data array throws the exported `RuntimeError`, whose message matches the one of [`interpret::Error`](crate::interpret::Error) and which has the output written before it.
+	///
+	/// # Errors
+	///
+	/// Returns `Err` iff writing to `out` returns `Err`.
+	pub fn render_js(&self, mut out: impl io::Write) -> io::Result<()> {
+		let array = match T::BYTES {
+			1 => "Uint8Array",
+			2 => "Uint16Array",
+			_ => "Uint32Array",
+		};
+		write!(out, "{PRELUDE}")?;
+		writeln!(out, "export function run(input) {{")?;
+		writeln!(
+			out,
+			"\tconst tape = new {array}({});",
+			self.recommended_array_size()
+		)?;
+		write!(out, "{HELPERS}")?;
+
+		let mut depth = 1;
+		let mut idx = 0;
+		while let Some(&instruction) = self.instructions.get(idx) {
+			idx += 1;
+			if matches!(instruction, Instruction::LoopEnd(..)) {
+				depth -= 1;
+			}
+			write!(out, "{}", "\t".repeat(depth))?;
+
+			// typed arrays wrap values when storing them, so only multiplication needs care to not lose precision
+			match instruction {
+				Instruction::Set(value) => writeln!(out, "tape[p] = {value};"),
+				Instruction::Inc(amount) => writeln!(out, "tape[p] += {amount};"),
+				Instruction::Dec(amount) => writeln!(out, "tape[p] -= {amount};"),
+				Instruction::IncPtr(amount) => writeln!(out, "right({amount});"),
+				Instruction::DecPtr(amount) => writeln!(out, "left({amount});"),
+				Instruction::Write => writeln!(out, "output.push(tape[p] & 255);"),
+				Instruction::Read => writeln!(out, "tape[p] = read();"),
+				Instruction::LoopStart(..) => {
+					depth += 1;
+					writeln!(out, "while (tape[p] !== 0) {{")
+				}
+				Instruction::LoopEnd(..) => writeln!(out, "}}"),
+				Instruction::MulAdd(offset, factor) => writeln!(
+					out,
+					"if (tape[p] !== 0) {{ tape[at({offset})] += Math.imul(tape[p], {factor}); }}"
+				),
+				Instruction::ScanRight(stride) => {
+					writeln!(out, "while (tape[p] !== 0) {{ right({stride}); }}")
+				}
+				Instruction::ScanLeft(stride) => {
+					writeln!(out, "while (tape[p] !== 0) {{ left({stride}); }}")
+				}
+				Instruction::SetAt(offset, value) => writeln!(out, "tape[at({offset})] = {value};"),
+				Instruction::AddAt(offset, amount) => writeln!(out, "tape[at({offset})] += {amount};"),
+				Instruction::ReadAt(offset) => {
+					writeln!(out, "{{ const idx = at({offset}); tape[idx] = read(); }}")
+				}
+				Instruction::WriteAt(offset) => writeln!(out, "output.push(tape[at({offset})] & 255);"),
+				Instruction::WriteConst(..) => {
+					let bytes: Vec<String> = self.instructions[idx - 1..]
+						.iter()
+						.map_while(|instruction| match instruction {
+							Instruction::WriteConst(byte) => Some(byte.to_string()),
+							_ => None,
+						})
+						.collect();
+					idx += bytes.len() - 1;
+					writeln!(out, "output.push({});", bytes.join(", "))
+				}
+			}?;
+		}
+
+		writeln!(out, "\treturn Uint8Array.from(output);")?;
+		writeln!(out, "}}")
+	}
+}
+
+const PRELUDE: &str = r#"/** Thrown when the pointer moves out of the data array. */
+export class RuntimeError extends Error {
+	constructor(message, output) {
+		super(message);
+		this.name = "RuntimeError";
+		/** The output written before the error. */
+		this.output = output;
+	}
+}
+
+/**
+ * Run the program with `input`, reading zero past its end.
+ *
+ * @param {Uint8Array} input
+ * @returns {Uint8Array} the output
+ * @throws {RuntimeError} if the pointer moves out of the data array
+ */
+"#;
+
+const HELPERS: &str = r#"	const output = [];
+	let p = 0;
+	let inputIdx = 0;
+	const overflow = () => new RuntimeError("runtime overflowed its data array", Uint8Array.from(output));
+	const underflow = () => new RuntimeError("runtime underflowed its data array", Uint8Array.from(output));
+	const right = (amount) => {
+		p += amount;
+		if (p >= tape.length) throw overflow();
+	};
+	const left = (amount) => {
+		p -= amount;
+		if (p < 0) throw underflow();
+	};
+	const at = (offset) => {
+		const idx = p + offset;
+		if (idx < 0) throw underflow();
+		if (idx >= tape.length) throw overflow();
+		return idx;
+	};
+	const read = () => (inputIdx < input.length ? input[inputIdx++] : 0);
+"#;
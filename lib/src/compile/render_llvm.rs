@@ -0,0 +1,223 @@
+use std::io;
+
+use super::{InstructionStream, PointerRange};
+use crate::cell_type::CellType;
+use crate::instruction::Instruction;
+
+impl<T: CellType> InstructionStream<T> {
+	/// Renders this instruction stream as an LLVM IR module to the writer `out`, which can be compiled with `llc` or optimized with `opt`.
+	///
+	/// The data array is a global of `iN` cells for the cell type, and the module declares `getchar` and `putchar` from libc and defines `main`.
+	/// Each loop has a basic block for its body and one for after it.
+	/// The pointer is kept on the stack, which `opt` promotes to registers.
+	/// Like [`render_c`](Self::render_c), nothing checks that the pointer stays in the data array.
+	///
+	/// # Errors
+	///
+	/// Returns `Err` iff writing to `out` returns `Err`.
+	pub fn render_llvm(&self, out: impl io::Write) -> io::Result<()> {
+		// there are no bounds checks, so make room for cells left of the start rather than accessing outside of the array
+		let start = match self.pointer_range() {
+			PointerRange::Bounded { min, .. } => min.unsigned_abs(),
+			PointerRange::Unbounded => 0,
+		};
+		let mut function = Function {
+			out,
+			cell: match T::BYTES {
+				1 => "i8",
+				2 => "i16",
+				_ => "i32",
+			},
+			values: 0,
+		};
+		function.header(self.recommended_array_size() as u64 + start, start)?;
+		for (idx, &instruction) in self.instructions.iter().enumerate() {
+			function.instruction(idx, instruction)?;
+		}
+		writeln!(function.out, "  ret i32 0\n}}")
+	}
+}
+
+/// Renders the body of `main`.
+struct Function<W> {
+	out: W,
+	/// The type of cells.
+	cell: &'static str,
+	/// The number of values defined so far, which are named `%v0` onwards.
+	values: usize,
+}
+
+impl<W: io::Write> Function<W> {
+	fn header(&mut self, tape_len: u64, start: u64) -> io::Result<()> {
+		let cell = self.cell;
+		writeln!(
+			self.out,
+			"@tape = internal global [{tape_len} x {cell}] zeroinitializer\n"
+		)?;
+		writeln!(self.out, "declare i32 @getchar()")?;
+		writeln!(self.out, "declare i32 @putchar(i32)\n")?;
+		writeln!(self.out, "define i32 @main() {{")?;
+		writeln!(self.out, "entry:")?;
+		writeln!(self.out, "  %p = alloca i64")?;
+		writeln!(self.out, "  store i64 {start}, ptr %p")
+	}
+
+	/// Write an instruction that defines a new value, returning its name.
+	fn define(&mut self, instruction: &str) -> io::Result<String> {
+		let name = format!("%v{}", self.values);
+		self.values += 1;
+		writeln!(self.out, "  {name} = {instruction}")?;
+		Ok(name)
+	}
+
+	/// Get the address of the cell at `offset` from the pointer.
+	fn address(&mut self, offset: i32) -> io::Result<String> {
+		let mut index = self.define("load i64, ptr %p")?;
+		if offset != 0 {
+			index = self.define(&format!("add i64 {index}, {offset}"))?;
+		}
+		let cell = self.cell;
+		self.define(&format!(
+			"getelementptr inbounds {cell}, ptr @tape, i64 {index}"
+		))
+	}
+
+	fn load(&mut self, address: &str) -> io::Result<String> {
+		let cell = self.cell;
+		self.define(&format!("load {cell}, ptr {address}"))
+	}
+
+	fn store(&mut self, value: &str, address: &str) -> io::Result<()> {
+		writeln!(self.out, "  store {} {value}, ptr {address}", self.cell)
+	}
+
+	/// Change the cell at `address` with `operation` and `amount`, which may be a value.
+	fn modify(&mut self, address: &str, operation: &str, amount: &str) -> io::Result<()> {
+		let old = self.load(address)?;
+		let cell = self.cell;
+		let new = self.define(&format!("{operation} {cell} {old}, {amount}"))?;
+		self.store(&new, address)
+	}
+
+	/// Move the pointer with `operation`, which is `add` or `sub`.
+	fn move_pointer(&mut self, operation: &str, amount: u32) -> io::Result<()> {
+		let old = self.define("load i64, ptr %p")?;
+		let new = self.define(&format!("{operation} i64 {old}, {amount}"))?;
+		writeln!(self.out, "  store i64 {new}, ptr %p")
+	}
+
+	/// Branch to `zero` if the current cell is zero, and `nonzero` otherwise.
+	fn branch(&mut self, zero: &str, nonzero: &str) -> io::Result<()> {
+		let address = self.address(0)?;
+		let value = self.load(&address)?;
+		let cell = self.cell;
+		let is_zero = self.define(&format!("icmp eq {cell} {value}, 0"))?;
+		writeln!(
+			self.out,
+			"  br i1 {is_zero}, label %{zero}, label %{nonzero}"
+		)
+	}
+
+	fn scan(&mut self, idx: usize, operation: &str, stride: u32) -> io::Result<()> {
+		writeln!(self.out, "  br label %scan{idx}\nscan{idx}:")?;
+		self.branch(&format!("scan{idx}.end"), &format!("scan{idx}.step"))?;
+		writeln!(self.out, "scan{idx}.step:")?;
+		self.move_pointer(operation, stride)?;
+		writeln!(self.out, "  br label %scan{idx}\nscan{idx}.end:")
+	}
+
+	fn read(&mut self, address: &str) -> io::Result<()> {
+		let byte = self.define("call i32 @getchar()")?;
+		// the end of the input reads zero
+		let eof = self.define(&format!("icmp eq i32 {byte}, -1"))?;
+		let mut value = self.define(&format!("select i1 {eof}, i32 0, i32 {byte}"))?;
+		let cell = self.cell;
+		if cell != "i32" {
+			value = self.define(&format!("trunc i32 {value} to {cell}"))?;
+		}
+		self.store(&value, address)
+	}
+
+	fn write(&mut self, address: &str) -> io::Result<()> {
+		// `putchar` only writes the low byte
+		let mut value = self.load(address)?;
+		let cell = self.cell;
+		if cell != "i32" {
+			value = self.define(&format!("zext {cell} {value} to i32"))?;
+		}
+		self.define(&format!("call i32 @putchar(i32 {value})"))?;
+		Ok(())
+	}
+
+	fn instruction<T: CellType>(
+		&mut self,
+		idx: usize,
+		instruction: Instruction<T>,
+	) -> io::Result<()> {
+		let number = |value: T| -> u32 { value.into() };
+		match instruction {
+			Instruction::Set(value) => {
+				let address = self.address(0)?;
+				self.store(&number(value).to_string(), &address)
+			}
+			Instruction::Inc(amount) => {
+				let address = self.address(0)?;
+				self.modify(&address, "add", &number(amount.into()).to_string())
+			}
+			Instruction::Dec(amount) => {
+				let address = self.address(0)?;
+				self.modify(&address, "sub", &number(amount.into()).to_string())
+			}
+			Instruction::IncPtr(amount) => self.move_pointer("add", amount.get()),
+			Instruction::DecPtr(amount) => self.move_pointer("sub", amount.get()),
+			Instruction::Write => {
+				let address = self.address(0)?;
+				self.write(&address)
+			}
+			Instruction::Read => {
+				let address = self.address(0)?;
+				self.read(&address)
+			}
+			Instruction::LoopStart(..) => {
+				self.branch(&format!("loop{idx}.end"), &format!("loop{idx}"))?;
+				writeln!(self.out, "loop{idx}:")
+			}
+			Instruction::LoopEnd(start) => {
+				self.branch(&format!("loop{start}.end"), &format!("loop{start}"))?;
+				writeln!(self.out, "loop{start}.end:")
+			}
+			Instruction::MulAdd(offset, factor) => {
+				let address = self.address(0)?;
+				let value = self.load(&address)?;
+				// adding a zero product leaves the target unchanged, so this doesn't check the current cell first
+				let cell = self.cell;
+				let factor = number(factor.into());
+				let product = self.define(&format!("mul {cell} {value}, {factor}"))?;
+				let target = self.address(offset)?;
+				self.modify(&target, "add", &product)
+			}
+			Instruction::ScanRight(stride) => self.scan(idx, "add", stride.get()),
+			Instruction::ScanLeft(stride) => self.scan(idx, "sub", stride.get()),
+			Instruction::SetAt(offset, value) => {
+				let address = self.address(offset)?;
+				self.store(&number(value).to_string(), &address)
+			}
+			Instruction::AddAt(offset, amount) => {
+				let address = self.address(offset)?;
+				self.modify(&address, "add", &number(amount.into()).to_string())
+			}
+			Instruction::ReadAt(offset) => {
+				let address = self.address(offset)?;
+				self.read(&address)
+			}
+			Instruction::WriteAt(offset) => {
+				let address = self.address(offset)?;
+				self.write(&address)
+			}
+			Instruction::WriteConst(byte) => {
+				self.define(&format!("call i32 @putchar(i32 {byte})"))?;
+				Ok(())
+			}
+		}
+	}
+}
@@ -0,0 +1,192 @@
+use std::io;
+
+use super::InstructionStream;
+use crate::cell_type::CellType;
+use crate::instruction::Instruction;
+
+/// Python can't compile more than 20 nested loops in one function, so deeper loops are put in functions of their own.
+/// Scans are loops too, so this leaves room for one.
+const MAX_LOOPS: usize = 19;
+
+impl<T: CellType> InstructionStream<T> {
+	/// Renders this instruction stream as a Python 3 script to the writer `out`.
+	///
+	/// The script reads from `sys.stdin.buffer` and writes to `sys.stdout.buffer`, and reading past the end of the input reads zero.
+	/// The data array is a `bytearray` for 8-bit cells, or an `array` otherwise, with the [recommended size](Self::recommended_array_size), and every access is checked just like when interpreting.
+	/// Errors are printed to stderr with the messages of [`interpret::Error`](crate::interpret::Error), and exit with status 1.
+	/// The script also has a `run` function that takes the input and output as binary files, so it can be imported as a module.
+	///
+	/// # Errors
+	///
+	/// Returns `Err` iff writing to `out` returns `Err`.
+	pub fn render_python(&self, mut out: impl io::Write) -> io::Result<()> {
+		let (import, tape) = match T::BYTES {
+			1 => ("", "bytearray(TAPE_LEN)"),
+			2 => ("from array import array\n", r#"array("H", [0]) * TAPE_LEN"#),
+			// `L` is at least 32 bits, while `I` may not be
+			_ => ("from array import array\n", r#"array("L", [0]) * TAPE_LEN"#),
+		};
+		writeln!(out, "#!/usr/bin/env python3\nimport sys\n{import}")?;
+		writeln!(out, "TAPE_LEN = {}", self.recommended_array_size())?;
+		// bytearrays only hold bytes and arrays only hold their type, so changes are masked to wrap
+		writeln!(out, "MASK = {:#x}", T::MAX)?;
+		write!(out, "{PRELUDE}")?;
+		writeln!(out, "\ttape = {tape}")?;
+		writeln!(out, "\tp = 0")?;
+
+		// writing only writes the low byte of the cell
+		let byte = if T::BYTES == 1 { "" } else { " & 0xff" };
+		let mut indent = 1;
+		// the loops in the current function, and for each open loop, the number of loops around it if it's in a function of its own
+		let mut loops = 0;
+		let mut open = Vec::new();
+		let mut idx = 0;
+		while let Some(&instruction) = self.instructions.get(idx) {
+			idx += 1;
+			if let Instruction::LoopEnd(start) = instruction {
+				indent -= 1;
+				loops -= 1;
+				if let Some(outer) = open.pop().flatten() {
+					writeln!(out, "{}return p", "\t".repeat(indent))?;
+					indent -= 1;
+					writeln!(out, "{}p = loop{start}(p)", "\t".repeat(indent))?;
+					loops = outer;
+				}
+				continue;
+			}
+			write!(out, "{}", "\t".repeat(indent))?;
+
+			match instruction {
+				Instruction::Set(value) => writeln!(out, "tape[p] = {value}"),
+				Instruction::Inc(amount) => writeln!(out, "tape[p] = (tape[p] + {amount}) & MASK"),
+				Instruction::Dec(amount) => writeln!(out, "tape[p] = (tape[p] - {amount}) & MASK"),
+				Instruction::IncPtr(amount) => writeln!(out, "p = right(p, {amount})"),
+				Instruction::DecPtr(amount) => writeln!(out, "p = left(p, {amount})"),
+				Instruction::Write => writeln!(out, "write(bytes((tape[p]{byte},)))"),
+				Instruction::Read => writeln!(out, "tape[p] = read()"),
+				Instruction::LoopStart(..) => {
+					if loops == MAX_LOOPS {
+						writeln!(out, "def loop{}(p):", idx - 1)?;
+						indent += 1;
+						write!(out, "{}", "\t".repeat(indent))?;
+						open.push(Some(loops));
+						loops = 0;
+					} else {
+						open.push(None);
+					}
+					indent += 1;
+					loops += 1;
+					writeln!(out, "while tape[p]:")?;
+					if let Some(Instruction::LoopEnd(..)) = self.instructions.get(idx) {
+						writeln!(out, "{}pass", "\t".repeat(indent))?;
+					}
+					Ok(())
+				}
+				Instruction::LoopEnd(..) => unreachable!(),
+				Instruction::MulAdd(offset, factor) => writeln!(
+					out,
+					"if tape[p]: idx = at(p, {offset}); tape[idx] = (tape[idx] + tape[p] * {factor}) & MASK"
+				),
+				Instruction::ScanRight(stride) => writeln!(out, "while tape[p]: p = right(p, {stride})"),
+				Instruction::ScanLeft(stride) => writeln!(out, "while tape[p]: p = left(p, {stride})"),
+				Instruction::SetAt(offset, value) => {
+					writeln!(out, "idx = at(p, {offset}); tape[idx] = {value}")
+				}
+				Instruction::AddAt(offset, amount) => writeln!(
+					out,
+					"idx = at(p, {offset}); tape[idx] = (tape[idx] + {amount}) & MASK"
+				),
+				Instruction::ReadAt(offset) => writeln!(out, "idx = at(p, {offset}); tape[idx] = read()"),
+				Instruction::WriteAt(offset) => {
+					writeln!(out, "write(bytes((tape[at(p, {offset})]{byte},)))")
+				}
+				Instruction::WriteConst(..) => {
+					let bytes: Vec<u8> = self.instructions[idx - 1..]
+						.iter()
+						.map_while(|instruction| match instruction {
+							Instruction::WriteConst(byte) => Some(*byte),
+							_ => None,
+						})
+						.collect();
+					idx += bytes.len() - 1;
+					let escaped: Vec<u8> = bytes
+						.into_iter()
+						.flat_map(std::ascii::escape_default)
+						.collect();
+					writeln!(out, "write(b\"{}\")", String::from_utf8_lossy(&escaped))
+				}
+			}?;
+		}
+
+		writeln!(out, "\tflush()")?;
+		write!(out, "{MAIN}")
+	}
+}
+
+const PRELUDE: &str = r#"
+
+class Error(Exception):
+	"""An error that stops the program, with the same message as when interpreting."""
+
+
+def right(p, amount):
+	p += amount
+	if p >= TAPE_LEN:
+		raise Error("runtime overflowed its data array")
+	return p
+
+
+def left(p, amount):
+	p -= amount
+	if p < 0:
+		raise Error("runtime underflowed its data array")
+	return p
+
+
+def at(p, offset):
+	idx = p + offset
+	if idx < 0:
+		raise Error("runtime underflowed its data array")
+	if idx >= TAPE_LEN:
+		raise Error("runtime overflowed its data array")
+	return idx
+
+
+def run(stdin, stdout):
+	"""Run the program, reading from the binary file `stdin` and writing to the binary file `stdout`.
+
+	Reading past the end of the input reads zero, and `stdout` is flushed before every read and at the end.
+	Raises `Error` if the pointer moves out of the data array, or reading or writing fails.
+	"""
+
+	def read():
+		flush()
+		try:
+			byte = stdin.read(1)
+		except OSError as error:
+			raise Error(f"IO error while reading from input: {error}") from error
+		return byte[0] if byte else 0
+
+	def write(data):
+		try:
+			stdout.write(data)
+		except OSError as error:
+			raise Error(f"IO error while writing to output: {error}") from error
+
+	def flush():
+		try:
+			stdout.flush()
+		except OSError as error:
+			raise Error(f"IO error while writing to output: {error}") from error
+
+"#;
+
+const MAIN: &str = r#"
+
+if __name__ == "__main__":
+	try:
+		run(sys.stdin.buffer, sys.stdout.buffer)
+	except Error as error:
+		print(f"error: {error}", file=sys.stderr)
+		sys.exit(1)
+"#;
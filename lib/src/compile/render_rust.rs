@@ -0,0 +1,348 @@
+use std::io;
+
+use super::InstructionStream;
+use crate::cell_type::CellType;
+use crate::instruction::Instruction;
+
+/// Options for [`InstructionStream::render_rust`].
+///
+/// The default renders a standalone program.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct RenderRustOptions {
+	library: bool,
+}
+
+impl RenderRustOptions {
+	/// Render only the `run` function and what it needs, without a `main` function, so the code can be used as a module.
+	#[must_use]
+	pub fn library(self, enabled: bool) -> Self {
+		Self { library: enabled }
+	}
+}
+
+/// The helpers that the rendered code needs, so that unused ones aren't rendered.
+#[allow(clippy::struct_excessive_bools)] // each one is a helper
+#[derive(Default)]
+struct Helpers {
+	/// Whether the data array is used at all.
+	tape: bool,
+	/// Whether the value of any cell is used.
+	cells: bool,
+	add: bool,
+	sub: bool,
+	right: bool,
+	left: bool,
+	at: bool,
+	scan_right: bool,
+	scan_left: bool,
+	read: bool,
+	write: bool,
+}
+
+impl Helpers {
+	fn of<T: CellType>(instructions: &[Instruction<T>]) -> Self {
+		let mut helpers = Self::default();
+		for instruction in instructions {
+			helpers.tape |= !matches!(instruction, Instruction::WriteConst(..));
+			helpers.cells |= !matches!(
+				instruction,
+				Instruction::WriteConst(..)
+					| Instruction::IncPtr(..)
+					| Instruction::DecPtr(..)
+					| Instruction::Set(..)
+					| Instruction::SetAt(..)
+					| Instruction::Read
+					| Instruction::ReadAt(..)
+			);
+			helpers.write |= matches!(
+				instruction,
+				Instruction::Write | Instruction::WriteAt(..) | Instruction::WriteConst(..)
+			);
+			match instruction {
+				Instruction::Inc(..) | Instruction::AddAt(..) => {
+					helpers.add = true;
+					helpers.at |= matches!(instruction, Instruction::AddAt(..));
+				}
+				Instruction::Dec(..) => helpers.sub = true,
+				Instruction::MulAdd(..) => {
+					helpers.add = true;
+					helpers.at = true;
+				}
+				Instruction::IncPtr(..) => helpers.right = true,
+				Instruction::DecPtr(..) => helpers.left = true,
+				Instruction::ScanRight(..) => helpers.scan_right = true,
+				Instruction::ScanLeft(..) => helpers.scan_left = true,
+				Instruction::Read => helpers.read = true,
+				Instruction::ReadAt(..) => {
+					helpers.read = true;
+					helpers.at = true;
+				}
+				Instruction::SetAt(..) | Instruction::WriteAt(..) => helpers.at = true,
+				_ => {}
+			}
+		}
+		helpers
+	}
+}
+
+impl<T: CellType> InstructionStream<T> {
+	/// Renders this instruction stream as a Rust program to the writer `out`.
+	///
+	/// The program forbids unsafe code.
+	/// It has a `run` function that takes input and output and returns an `Error` type whose variants and messages match the ones of [`interpret::Error`](crate::interpret::Error) that can occur.
+	/// The data array has the [recommended size](Self::recommended_array_size), and every access is checked just like when interpreting.
+	///
+	/// # Errors
+	///
+	/// Returns `Err` iff writing to `out` returns `Err`.
+	pub fn render_rust(&self, mut out: impl io::Write, options: RenderRustOptions) -> io::Result<()> {
+		let helpers = Helpers::of(&self.instructions);
+		render_prelude::<T>(&mut out, &helpers, self.recommended_array_size())?;
+
+		writeln!(
+			out,
+			"/// Run the program, reading from `input` and writing to `output`."
+		)?;
+		writeln!(out, "///")?;
+		writeln!(
+			out,
+			"/// Reading past the end of the input reads zero, and `output` is flushed before every read."
+		)?;
+		writeln!(
+			out,
+			"pub fn run(input: impl Read, output: impl Write) -> Result<(), Error> {{"
+		)?;
+		if helpers.read {
+			writeln!(out, "\tlet input = &mut input.bytes();")?;
+		} else {
+			writeln!(out, "\tlet _ = input;")?;
+		}
+		writeln!(out, "\tlet output = &mut io::BufWriter::new(output);")?;
+		if helpers.tape {
+			writeln!(
+				out,
+				"\tlet tape = &mut Tape {{ cells: vec![0; TAPE_LEN], ptr: 0 }};"
+			)?;
+		}
+
+		let mut depth = 1;
+		let mut idx = 0;
+		while let Some(&instruction) = self.instructions.get(idx) {
+			idx += 1;
+			if matches!(instruction, Instruction::LoopEnd(..)) {
+				depth -= 1;
+			}
+			write!(out, "{}", "\t".repeat(depth))?;
+
+			// the casts truncate like `Write` does
+			let byte = if T::BYTES == 1 { "" } else { " as u8" };
+			match instruction {
+				Instruction::Set(value) => writeln!(out, "tape.cells[tape.ptr] = {value};"),
+				Instruction::Inc(amount) => writeln!(out, "tape.add(tape.ptr, {amount});"),
+				Instruction::Dec(amount) => writeln!(out, "tape.sub(tape.ptr, {amount});"),
+				Instruction::IncPtr(amount) => writeln!(out, "tape.right({amount})?;"),
+				Instruction::DecPtr(amount) => writeln!(out, "tape.left({amount})?;"),
+				Instruction::Write => writeln!(out, "write(output, &[tape.cells[tape.ptr]{byte}])?;"),
+				Instruction::Read => writeln!(out, "tape.cells[tape.ptr] = read(input, output)?;"),
+				Instruction::LoopStart(..) => {
+					depth += 1;
+					writeln!(out, "while tape.cells[tape.ptr] != 0 {{")
+				}
+				Instruction::LoopEnd(..) => writeln!(out, "}}"),
+				Instruction::MulAdd(offset, factor) => writeln!(
+					out,
+					"if tape.cells[tape.ptr] != 0 {{ let idx = tape.at({offset})?; tape.add(idx, tape.cells[tape.ptr].wrapping_mul({factor})); }}"
+				),
+				Instruction::ScanRight(stride) => writeln!(out, "tape.scan_right({stride})?;"),
+				Instruction::ScanLeft(stride) => writeln!(out, "tape.scan_left({stride})?;"),
+				Instruction::SetAt(offset, value) => {
+					writeln!(out, "let idx = tape.at({offset})?; tape.cells[idx] = {value};")
+				}
+				Instruction::AddAt(offset, amount) => {
+					writeln!(out, "let idx = tape.at({offset})?; tape.add(idx, {amount});")
+				}
+				Instruction::ReadAt(offset) => writeln!(
+					out,
+					"let idx = tape.at({offset})?; tape.cells[idx] = read(input, output)?;"
+				),
+				Instruction::WriteAt(offset) => writeln!(
+					out,
+					"let idx = tape.at({offset})?; write(output, &[tape.cells[idx]{byte}])?;"
+				),
+				Instruction::WriteConst(..) => {
+					let len = self.instructions[idx - 1..]
+						.iter()
+						.take_while(|instruction| matches!(instruction, Instruction::WriteConst(..)))
+						.count();
+					let bytes: Vec<u8> = self.instructions[idx - 1..idx - 1 + len]
+						.iter()
+						.flat_map(|instruction| match instruction {
+							Instruction::WriteConst(byte) => std::ascii::escape_default(*byte),
+							_ => unreachable!(),
+						})
+						.collect();
+					idx += len - 1;
+					writeln!(out, "write(output, b\"{}\")?;", String::from_utf8_lossy(&bytes))
+				}
+			}?;
+		}
+
+		writeln!(out, "\toutput.flush().map_err(Error::OutputIo)")?;
+		writeln!(out, "}}")?;
+
+		if !options.library {
+			write!(out, "\n{MAIN}")?;
+		}
+		Ok(())
+	}
+}
+
+/// Render everything before the `run` function: the error type, the data array and its helpers, and the IO helpers.
+fn render_prelude<T: CellType>(
+	out: &mut impl io::Write,
+	helpers: &Helpers,
+	tape_len: usize,
+) -> io::Result<()> {
+	let cell = match T::BYTES {
+		1 => "u8",
+		2 => "u16",
+		_ => "u32",
+	};
+	write!(
+		out,
+		r#"#![forbid(unsafe_code)]
+
+use std::fmt;
+use std::io::{{self, Read, Write}};
+
+/// Errors that can occur while running the program.
+#[derive(Debug)]
+pub enum Error {{
+	/// The pointer moved past the end of the data array.
+	Overflow,
+	/// The pointer moved before the start of the data array.
+	Underflow,
+	/// Reading from the input failed.
+	InputIo(io::Error),
+	/// Writing to the output failed.
+	OutputIo(io::Error),
+}}
+
+impl fmt::Display for Error {{
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {{
+		match self {{
+			Self::Overflow => write!(f, "runtime overflowed its data array"),
+			Self::Underflow => write!(f, "runtime underflowed its data array"),
+			Self::InputIo(error) => write!(f, "IO error while reading from input: {{error}}"),
+			Self::OutputIo(error) => write!(f, "IO error while writing to output: {{error}}"),
+		}}
+	}}
+}}
+
+impl std::error::Error for Error {{}}
+"#
+	)?;
+
+	if helpers.tape || helpers.read {
+		write!(out, "\ntype Cell = {cell};\n")?;
+	}
+	if helpers.tape {
+		write!(out, "\nconst TAPE_LEN: usize = {tape_len};\n\n")?;
+		if !helpers.cells {
+			writeln!(out, "#[allow(dead_code)] // the program never reads a cell")?;
+		}
+		let methods = [
+			(helpers.add, ADD),
+			(helpers.sub, SUB),
+			(helpers.right, RIGHT),
+			(helpers.left, LEFT),
+			(helpers.at, AT),
+			(helpers.scan_right, SCAN_RIGHT),
+			(helpers.scan_left, SCAN_LEFT),
+		];
+		let methods: Vec<_> = methods
+			.into_iter()
+			.filter_map(|(used, method)| used.then_some(method))
+			.collect();
+		write!(
+			out,
+			"struct Tape {{\n\tcells: Vec<Cell>,\n\tptr: usize,\n}}\n\nimpl Tape {{\n{}}}\n",
+			methods.join("\n")
+		)?;
+	}
+	if helpers.read {
+		write!(out, "\n{READ}")?;
+	}
+	if helpers.write {
+		write!(out, "\n{WRITE}")?;
+	}
+	writeln!(out)
+}
+
+const MAIN: &str = "fn main() {
+	if let Err(error) = run(io::stdin().lock(), io::stdout().lock()) {
+		eprintln!(\"error: {error}\");
+		std::process::exit(1);
+	}
+}
+";
+
+const READ: &str =
+	"fn read(input: &mut io::Bytes<impl Read>, output: &mut impl Write) -> Result<Cell, Error> {
+	output.flush().map_err(Error::OutputIo)?;
+	match input.next() {
+		Some(byte) => byte.map(Cell::from).map_err(Error::InputIo),
+		None => Ok(0),
+	}
+}
+";
+
+const WRITE: &str = "fn write(output: &mut impl Write, bytes: &[u8]) -> Result<(), Error> {
+	output.write_all(bytes).map_err(Error::OutputIo)
+}
+";
+
+const ADD: &str = "	fn add(&mut self, idx: usize, amount: Cell) {
+		self.cells[idx] = self.cells[idx].wrapping_add(amount);
+	}
+";
+
+const SUB: &str = "	fn sub(&mut self, idx: usize, amount: Cell) {
+		self.cells[idx] = self.cells[idx].wrapping_sub(amount);
+	}
+";
+
+const RIGHT: &str = "	fn right(&mut self, amount: usize) -> Result<(), Error> {
+		self.ptr = self.ptr.checked_add(amount).filter(|&ptr| ptr < self.cells.len()).ok_or(Error::Overflow)?;
+		Ok(())
+	}
+";
+
+const LEFT: &str = "	fn left(&mut self, amount: usize) -> Result<(), Error> {
+		self.ptr = self.ptr.checked_sub(amount).ok_or(Error::Underflow)?;
+		Ok(())
+	}
+";
+
+const AT: &str = "	fn at(&self, offset: isize) -> Result<usize, Error> {
+		if offset < 0 {
+			self.ptr.checked_sub(offset.unsigned_abs()).ok_or(Error::Underflow)
+		} else {
+			self.ptr.checked_add(offset.unsigned_abs()).filter(|&idx| idx < self.cells.len()).ok_or(Error::Overflow)
+		}
+	}
+";
+
+const SCAN_RIGHT: &str = "	fn scan_right(&mut self, stride: usize) -> Result<(), Error> {
+		let steps = self.cells[self.ptr..].iter().step_by(stride).position(|&cell| cell == 0).ok_or(Error::Overflow)?;
+		self.ptr += steps * stride;
+		Ok(())
+	}
+";
+
+const SCAN_LEFT: &str = "	fn scan_left(&mut self, stride: usize) -> Result<(), Error> {
+		let steps = self.cells[..=self.ptr].iter().rev().step_by(stride).position(|&cell| cell == 0).ok_or(Error::Underflow)?;
+		self.ptr -= steps * stride;
+		Ok(())
+	}
+";
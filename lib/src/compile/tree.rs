@@ -0,0 +1,197 @@
+//! A tree of loops, for working with loops structurally instead of through jump targets.
+//!
+//! A [`Block`] is a list of nodes, each either an [`Op`], which is any instruction other than a loop start or end, or a [`Loop`] with its own block as its body.
+//! Converting an instruction stream to a tree and back gives the same instructions and spans.
+
+use super::{Error, InstructionStream, PointerRange};
+use crate::cell_type::CellType;
+use crate::instruction::Instruction;
+use crate::span::Span;
+
+/// A list of nodes that run one after the other.
+pub type Block<T> = Vec<Node<T>>;
+
+/// A node of a loop tree.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Node<T: CellType> {
+	/// An instruction other than a loop start or end.
+	Op(Op<T>),
+	/// A loop.
+	Loop(Loop<T>),
+}
+
+/// An instruction other than a loop start or end.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Op<T: CellType> {
+	/// The instruction.
+	pub instruction: Instruction<T>,
+	/// The span of source code that the instruction came from, if tracked.
+	pub span: Option<Span>,
+}
+
+/// A loop, which runs its body while the current cell is nonzero.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Loop<T: CellType> {
+	/// The nodes in the loop.
+	pub body: Block<T>,
+	/// The span of source code that the loop start came from, if tracked.
+	pub start_span: Option<Span>,
+	/// The span of source code that the loop end came from, if tracked.
+	pub end_span: Option<Span>,
+}
+
+/// Build the loop tree of `instructions`, with `spans` as the span of each instruction if given.
+///
+/// Jump targets are ignored, so this can be used on instructions whose jump targets are out of date, like in a [`Pass`](super::Pass).
+///
+/// # Panics
+///
+/// Panics if the loops in `instructions` are unbalanced, or `spans` has a different length.
+#[must_use]
+pub fn build<T: CellType>(instructions: &[Instruction<T>], spans: Option<&[Span]>) -> Block<T> {
+	if let Some(spans) = spans {
+		assert_eq!(spans.len(), instructions.len(), "one span per instruction");
+	}
+
+	// the blocks of the loops that are currently open, with the span of their loop start
+	let mut open = Vec::new();
+	let mut block = Vec::new();
+	for (idx, &instruction) in instructions.iter().enumerate() {
+		let span = spans.map(|spans| spans[idx]);
+		match instruction {
+			Instruction::LoopStart(..) => open.push((std::mem::take(&mut block), span)),
+			Instruction::LoopEnd(..) => {
+				let (outer, start_span) = open.pop().expect("unmatched loop end");
+				let body = std::mem::replace(&mut block, outer);
+				block.push(Node::Loop(Loop {
+					body,
+					start_span,
+					end_span: span,
+				}));
+			}
+			_ => block.push(Node::Op(Op { instruction, span })),
+		}
+	}
+	assert!(open.is_empty(), "unmatched loop start");
+
+	block
+}
+
+/// Flatten a loop tree into instructions with up to date jump targets, and the span of each instruction if every node has spans.
+///
+/// # Panics
+///
+/// Panics if there are more than `u32::MAX` instructions.
+#[must_use]
+pub fn flatten<T: CellType>(block: Block<T>) -> (Vec<Instruction<T>>, Option<Vec<Span>>) {
+	fn flatten_into<T: CellType>(
+		block: Block<T>,
+		instructions: &mut Vec<Instruction<T>>,
+		spans: &mut Vec<Option<Span>>,
+	) {
+		for node in block {
+			match node {
+				Node::Op(op) => {
+					instructions.push(op.instruction);
+					spans.push(op.span);
+				}
+				Node::Loop(node) => {
+					let start = instructions.len();
+					instructions.push(Instruction::LoopStart(0));
+					spans.push(node.start_span);
+					flatten_into(node.body, instructions, spans);
+					let end = instructions.len();
+					instructions[start] = Instruction::LoopStart(end.try_into().unwrap());
+					instructions.push(Instruction::LoopEnd(start.try_into().unwrap()));
+					spans.push(node.end_span);
+				}
+			}
+		}
+	}
+
+	let mut instructions = Vec::new();
+	let mut spans = Vec::new();
+	flatten_into(block, &mut instructions, &mut spans);
+	(instructions, spans.into_iter().collect())
+}
+
+impl<T: CellType> InstructionStream<T> {
+	/// Build the loop tree of the stream.
+	///
+	/// See [`build`].
+	#[must_use]
+	pub fn to_tree(&self) -> Block<T> {
+		build(&self.instructions, self.spans.as_deref())
+	}
+
+	/// Create an instruction stream from a loop tree.
+	///
+	/// The stream tracks spans iff every node has them.
+	///
+	/// # Errors
+	///
+	/// Returns `Err` iff ops that are loop starts or ends leave unmatched loop starts or ends.
+	pub fn from_tree(block: Block<T>) -> Result<Self, Error> {
+		let (instructions, spans) = flatten(block);
+		let mut stream = Self {
+			instructions,
+			spans,
+			pointer_range: PointerRange::Unbounded,
+		};
+		stream.update_jump_points()?;
+		Ok(stream)
+	}
+}
+
+/// Visits the nodes of a loop tree in order.
+///
+/// Every method has a default, so implementations only override what they need.
+pub trait Visit<T: CellType> {
+	/// Visit an op. The default does nothing.
+	fn visit_op(&mut self, op: &Op<T>) {
+		let _ = op;
+	}
+
+	/// Visit a loop. The default visits its body with [`walk`].
+	fn visit_loop(&mut self, node: &Loop<T>) {
+		walk(self, &node.body);
+	}
+}
+
+/// Visit the nodes of `block` in order.
+pub fn walk<T: CellType, V: Visit<T> + ?Sized>(visitor: &mut V, block: &[Node<T>]) {
+	for node in block {
+		match node {
+			Node::Op(op) => visitor.visit_op(op),
+			Node::Loop(node) => visitor.visit_loop(node),
+		}
+	}
+}
+
+/// Rewrites the nodes of a loop tree in order, replacing each with any number of nodes.
+///
+/// Every method has a default, so implementations only override what they need.
+pub trait Rewrite<T: CellType> {
+	/// Rewrite an op. The default keeps it.
+	fn rewrite_op(&mut self, op: Op<T>) -> Block<T> {
+		vec![Node::Op(op)]
+	}
+
+	/// Rewrite a loop. The default rewrites its body with [`rewrite`] and keeps it.
+	fn rewrite_loop(&mut self, mut node: Loop<T>) -> Block<T> {
+		node.body = rewrite(self, node.body);
+		vec![Node::Loop(node)]
+	}
+}
+
+/// Rewrite the nodes of `block` in order.
+pub fn rewrite<T: CellType, R: Rewrite<T> + ?Sized>(rewriter: &mut R, block: Block<T>) -> Block<T> {
+	let mut rewritten = Vec::with_capacity(block.len());
+	for node in block {
+		rewritten.extend(match node {
+			Node::Op(op) => rewriter.rewrite_op(op),
+			Node::Loop(node) => rewriter.rewrite_loop(node),
+		});
+	}
+	rewritten
+}
@@ -0,0 +1,448 @@
+//! Compiling instruction streams to WebAssembly modules.
+//!
+//! [`InstructionStream::write_wasm`] writes a binary module that:
+//!
+//! - imports `env.read`, a function taking nothing and returning the next input byte as an `i32`, or 0 at the end of the input,
+//! - imports `env.write`, a function taking an output byte as an `i32` and returning nothing,
+//! - exports `memory`, the linear memory that holds the data array from address 0, with cells in little-endian order,
+//! - exports `run`, a function taking nothing that runs the program and returns one of the `STATUS_*` constants as an `i32`.
+
+use std::io;
+
+use super::InstructionStream;
+use crate::cell_type::CellType;
+use crate::instruction::Instruction;
+
+/// The status returned by `run` when the program finishes.
+pub const STATUS_OK: i32 = 0;
+
+/// The status returned by `run` when the pointer moves past the end of the data array.
+pub const STATUS_OVERFLOW: i32 = 1;
+
+/// The status returned by `run` when the pointer moves before the start of the data array.
+pub const STATUS_UNDERFLOW: i32 = 2;
+
+/// The bytes that every module starts with, the magic bytes and the format version.
+const HEADER: [u8; 8] = *b"\0asm\x01\0\0\0";
+
+/// The size of a page of linear memory.
+const PAGE_SIZE: u64 = 0x1_0000;
+
+/// The largest data array in bytes, so that adding two addresses in it never wraps.
+const MAX_TAPE_BYTES: u64 = 1 << 31;
+
+const SECTION_TYPE: u8 = 1;
+const SECTION_IMPORT: u8 = 2;
+const SECTION_FUNCTION: u8 = 3;
+const SECTION_MEMORY: u8 = 5;
+const SECTION_EXPORT: u8 = 7;
+const SECTION_CODE: u8 = 10;
+
+const TYPE_FUNC: u8 = 0x60;
+const TYPE_I32: u8 = 0x7f;
+const BLOCK_EMPTY: u8 = 0x40;
+
+const KIND_FUNC: u8 = 0;
+const KIND_MEMORY: u8 = 2;
+
+/// The function indices, imports first.
+const FUNC_READ: u32 = 0;
+const FUNC_WRITE: u32 = 1;
+const FUNC_RUN: u32 = 2;
+
+/// The local holding the address of the current cell.
+const LOCAL_POINTER: u32 = 0;
+/// The local holding the address of a cell at an offset.
+const LOCAL_ADDRESS: u32 = 1;
+
+const OP_BLOCK: u8 = 0x02;
+const OP_LOOP: u8 = 0x03;
+const OP_IF: u8 = 0x04;
+const OP_END: u8 = 0x0b;
+const OP_BR: u8 = 0x0c;
+const OP_BR_IF: u8 = 0x0d;
+const OP_RETURN: u8 = 0x0f;
+const OP_CALL: u8 = 0x10;
+const OP_LOCAL_GET: u8 = 0x20;
+const OP_LOCAL_SET: u8 = 0x21;
+const OP_LOCAL_TEE: u8 = 0x22;
+const OP_I32_LOAD: u8 = 0x28;
+const OP_I32_LOAD8_U: u8 = 0x2d;
+const OP_I32_LOAD16_U: u8 = 0x2f;
+const OP_I32_STORE: u8 = 0x36;
+const OP_I32_STORE8: u8 = 0x3a;
+const OP_I32_STORE16: u8 = 0x3b;
+const OP_I32_CONST: u8 = 0x41;
+const OP_I32_EQZ: u8 = 0x45;
+const OP_I32_LT_U: u8 = 0x49;
+const OP_I32_GE_U: u8 = 0x4f;
+const OP_I32_ADD: u8 = 0x6a;
+const OP_I32_SUB: u8 = 0x6b;
+const OP_I32_MUL: u8 = 0x6c;
+const OP_I32_AND: u8 = 0x71;
+
+impl<T: CellType> InstructionStream<T> {
+	/// Write the instruction stream as a binary WebAssembly module to the writer `out`.
+	///
+	/// The data array has the [recommended size](Self::recommended_array_size), and every access is checked just like when interpreting.
+	/// See the [module documentation](self) for the imports and exports.
+	///
+	/// # Errors
+	///
+	/// Returns `Err` iff writing to `out` returns `Err`, or the data array is larger than 2 GiB.
+	pub fn write_wasm(&self, mut out: impl io::Write) -> io::Result<()> {
+		let tape_bytes = u64::try_from(self.recommended_array_size())
+			.map_or(u64::MAX, |len| len.saturating_mul(T::BYTES.into()));
+		if tape_bytes > MAX_TAPE_BYTES {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"data array is too large for WebAssembly",
+			));
+		}
+
+		let mut module = HEADER.to_vec();
+
+		let mut types = Vec::new();
+		leb_u32(&mut types, 3);
+		// read: [] -> [i32]
+		types.extend([TYPE_FUNC, 0, 1, TYPE_I32]);
+		// write: [i32] -> []
+		types.extend([TYPE_FUNC, 1, TYPE_I32, 0]);
+		// run: [] -> [i32]
+		types.extend([TYPE_FUNC, 0, 1, TYPE_I32]);
+		section(&mut module, SECTION_TYPE, &types);
+
+		let mut imports = Vec::new();
+		leb_u32(&mut imports, 2);
+		for (name, ty) in [("read", 0), ("write", 1)] {
+			name_bytes(&mut imports, "env");
+			name_bytes(&mut imports, name);
+			imports.push(KIND_FUNC);
+			leb_u32(&mut imports, ty);
+		}
+		section(&mut module, SECTION_IMPORT, &imports);
+
+		let mut functions = Vec::new();
+		leb_u32(&mut functions, 1);
+		leb_u32(&mut functions, 2);
+		section(&mut module, SECTION_FUNCTION, &functions);
+
+		let mut memories = Vec::new();
+		leb_u32(&mut memories, 1);
+		// only a minimum number of pages
+		memories.push(0);
+		leb_u32(
+			&mut memories,
+			u32::try_from(tape_bytes.div_ceil(PAGE_SIZE)).unwrap_or(u32::MAX),
+		);
+		section(&mut module, SECTION_MEMORY, &memories);
+
+		let mut exports = Vec::new();
+		leb_u32(&mut exports, 2);
+		name_bytes(&mut exports, "run");
+		exports.push(KIND_FUNC);
+		leb_u32(&mut exports, FUNC_RUN);
+		name_bytes(&mut exports, "memory");
+		exports.push(KIND_MEMORY);
+		leb_u32(&mut exports, 0);
+		section(&mut module, SECTION_EXPORT, &exports);
+
+		let mut body = Body::<T> {
+			code: Vec::new(),
+			tape_bytes,
+			cell: std::marker::PhantomData,
+		};
+		// one group of two i32 locals
+		body.code.extend([1, 2, TYPE_I32]);
+		for &instruction in &self.instructions {
+			body.instruction(instruction);
+		}
+		body.status(STATUS_OK);
+		body.code.push(OP_END);
+
+		let mut code = Vec::new();
+		leb_u32(&mut code, 1);
+		leb_u32(&mut code, len_u32(body.code.len()));
+		code.extend(body.code);
+		section(&mut module, SECTION_CODE, &code);
+
+		out.write_all(&module)
+	}
+}
+
+/// The body of the `run` function.
+struct Body<T> {
+	code: Vec<u8>,
+	tape_bytes: u64,
+	cell: std::marker::PhantomData<T>,
+}
+
+impl<T: CellType> Body<T> {
+	fn instruction(&mut self, instruction: Instruction<T>) {
+		let bytes = |cells: u32| u64::from(cells) * u64::from(T::BYTES);
+		let cell = |value: T| -> u32 { value.into() };
+
+		match instruction {
+			Instruction::Set(value) => self.set(LOCAL_POINTER, cell(value)),
+			Instruction::Inc(amount) => self.add(LOCAL_POINTER, cell(amount.into()), OP_I32_ADD),
+			Instruction::Dec(amount) => self.add(LOCAL_POINTER, cell(amount.into()), OP_I32_SUB),
+			Instruction::IncPtr(amount) => self.right(bytes(amount.get())),
+			Instruction::DecPtr(amount) => self.left(bytes(amount.get())),
+			Instruction::Write => self.write(LOCAL_POINTER),
+			Instruction::Read => self.read(LOCAL_POINTER),
+			Instruction::LoopStart(..) => self.loop_start(),
+			Instruction::LoopEnd(..) => self.loop_end(),
+			Instruction::MulAdd(offset, factor) => {
+				// the target is only accessed, and so only checked, if the current cell is nonzero
+				self.load(LOCAL_POINTER);
+				self.code.extend([OP_IF, BLOCK_EMPTY]);
+				let address = self.address(offset);
+				self.local(OP_LOCAL_GET, address);
+				self.load(address);
+				self.load(LOCAL_POINTER);
+				self.i32_const(cell(factor.into()));
+				self.code.extend([OP_I32_MUL, OP_I32_ADD]);
+				self.store();
+				self.code.push(OP_END);
+			}
+			Instruction::ScanRight(stride) => {
+				self.loop_start();
+				self.right(bytes(stride.get()));
+				self.loop_end();
+			}
+			Instruction::ScanLeft(stride) => {
+				self.loop_start();
+				self.left(bytes(stride.get()));
+				self.loop_end();
+			}
+			Instruction::SetAt(offset, value) => {
+				let address = self.address(offset);
+				self.set(address, cell(value));
+			}
+			Instruction::AddAt(offset, amount) => {
+				let address = self.address(offset);
+				self.add(address, cell(amount.into()), OP_I32_ADD);
+			}
+			Instruction::ReadAt(offset) => {
+				let address = self.address(offset);
+				self.read(address);
+			}
+			Instruction::WriteAt(offset) => {
+				let address = self.address(offset);
+				self.write(address);
+			}
+			Instruction::WriteConst(byte) => {
+				self.i32_const(byte.into());
+				self.call(FUNC_WRITE);
+			}
+		}
+	}
+
+	fn loop_start(&mut self) {
+		self
+			.code
+			.extend([OP_BLOCK, BLOCK_EMPTY, OP_LOOP, BLOCK_EMPTY]);
+		self.load(LOCAL_POINTER);
+		self.code.extend([OP_I32_EQZ, OP_BR_IF, 1]);
+	}
+
+	fn loop_end(&mut self) {
+		self.code.extend([OP_BR, 0, OP_END, OP_END]);
+	}
+
+	/// Move the pointer right by `bytes`.
+	fn right(&mut self, bytes: u64) {
+		if bytes >= self.tape_bytes {
+			self.fail(STATUS_OVERFLOW);
+			return;
+		}
+		self.local(OP_LOCAL_GET, LOCAL_POINTER);
+		self.i32_const(len_u32(bytes));
+		self.code.push(OP_I32_ADD);
+		self.local(OP_LOCAL_TEE, LOCAL_POINTER);
+		self.fail_if_past_end();
+	}
+
+	/// Move the pointer left by `bytes`.
+	fn left(&mut self, bytes: u64) {
+		if bytes >= self.tape_bytes {
+			self.fail(STATUS_UNDERFLOW);
+			return;
+		}
+		self.fail_if_below(len_u32(bytes));
+		self.local(OP_LOCAL_GET, LOCAL_POINTER);
+		self.i32_const(len_u32(bytes));
+		self.code.push(OP_I32_SUB);
+		self.local(OP_LOCAL_SET, LOCAL_POINTER);
+	}
+
+	/// Check the address of the cell at `offset` from the pointer, and get the local that holds it.
+	fn address(&mut self, offset: i32) -> u32 {
+		if offset == 0 {
+			return LOCAL_POINTER;
+		}
+		let bytes = u64::from(offset.unsigned_abs()) * u64::from(T::BYTES);
+		if bytes >= self.tape_bytes {
+			self.fail(if offset < 0 {
+				STATUS_UNDERFLOW
+			} else {
+				STATUS_OVERFLOW
+			});
+			return LOCAL_ADDRESS;
+		}
+
+		if offset < 0 {
+			self.fail_if_below(len_u32(bytes));
+		}
+		self.local(OP_LOCAL_GET, LOCAL_POINTER);
+		self.i32_const(len_u32(bytes));
+		if offset < 0 {
+			self.code.push(OP_I32_SUB);
+			self.local(OP_LOCAL_SET, LOCAL_ADDRESS);
+		} else {
+			self.code.push(OP_I32_ADD);
+			self.local(OP_LOCAL_TEE, LOCAL_ADDRESS);
+			self.fail_if_past_end();
+		}
+		LOCAL_ADDRESS
+	}
+
+	/// Return [`STATUS_OVERFLOW`] if the address on the stack is past the end of the data array, consuming it.
+	fn fail_if_past_end(&mut self) {
+		self.i32_const(len_u32(self.tape_bytes));
+		self.code.extend([OP_I32_GE_U, OP_IF, BLOCK_EMPTY]);
+		self.fail(STATUS_OVERFLOW);
+		self.code.push(OP_END);
+	}
+
+	/// Return [`STATUS_UNDERFLOW`] if the pointer is less than `bytes`.
+	fn fail_if_below(&mut self, bytes: u32) {
+		self.local(OP_LOCAL_GET, LOCAL_POINTER);
+		self.i32_const(bytes);
+		self.code.extend([OP_I32_LT_U, OP_IF, BLOCK_EMPTY]);
+		self.fail(STATUS_UNDERFLOW);
+		self.code.push(OP_END);
+	}
+
+	fn fail(&mut self, status: i32) {
+		self.status(status);
+		self.code.push(OP_RETURN);
+	}
+
+	fn status(&mut self, status: i32) {
+		self.code.push(OP_I32_CONST);
+		leb_i32(&mut self.code, status);
+	}
+
+	fn set(&mut self, address: u32, value: u32) {
+		self.local(OP_LOCAL_GET, address);
+		self.i32_const(value);
+		self.store();
+	}
+
+	/// Add to or subtract from the cell at `address` with `op`, wrapping by storing only the low bytes.
+	fn add(&mut self, address: u32, amount: u32, op: u8) {
+		self.local(OP_LOCAL_GET, address);
+		self.load(address);
+		self.i32_const(amount);
+		self.code.push(op);
+		self.store();
+	}
+
+	fn read(&mut self, address: u32) {
+		self.local(OP_LOCAL_GET, address);
+		self.call(FUNC_READ);
+		self.store();
+	}
+
+	fn write(&mut self, address: u32) {
+		self.load(address);
+		self.i32_const(0xff);
+		self.code.push(OP_I32_AND);
+		self.call(FUNC_WRITE);
+	}
+
+	fn load(&mut self, address: u32) {
+		self.local(OP_LOCAL_GET, address);
+		let op = match T::BYTES {
+			1 => OP_I32_LOAD8_U,
+			2 => OP_I32_LOAD16_U,
+			_ => OP_I32_LOAD,
+		};
+		self.memory(op);
+	}
+
+	fn store(&mut self) {
+		let op = match T::BYTES {
+			1 => OP_I32_STORE8,
+			2 => OP_I32_STORE16,
+			_ => OP_I32_STORE,
+		};
+		self.memory(op);
+	}
+
+	/// Push a memory instruction with natural alignment and no offset.
+	fn memory(&mut self, op: u8) {
+		self
+			.code
+			.extend([op, T::BYTES.trailing_zeros().try_into().unwrap(), 0]);
+	}
+
+	fn local(&mut self, op: u8, local: u32) {
+		self.code.push(op);
+		leb_u32(&mut self.code, local);
+	}
+
+	fn call(&mut self, function: u32) {
+		self.code.push(OP_CALL);
+		leb_u32(&mut self.code, function);
+	}
+
+	/// Push a constant, which is signed in the encoding but used as unsigned.
+	#[allow(clippy::cast_possible_wrap)] // the bits are the same
+	fn i32_const(&mut self, value: u32) {
+		self.code.push(OP_I32_CONST);
+		leb_i32(&mut self.code, value as i32);
+	}
+}
+
+/// Convert a length to a `u32`, which the format uses for lengths.
+fn len_u32(len: impl TryInto<u32>) -> u32 {
+	len.try_into().ok().expect("length fits in a u32")
+}
+
+fn section(module: &mut Vec<u8>, id: u8, contents: &[u8]) {
+	module.push(id);
+	leb_u32(module, len_u32(contents.len()));
+	module.extend(contents);
+}
+
+fn name_bytes(out: &mut Vec<u8>, name: &str) {
+	leb_u32(out, len_u32(name.len()));
+	out.extend(name.as_bytes());
+}
+
+fn leb_u32(out: &mut Vec<u8>, mut value: u32) {
+	loop {
+		let byte = value.to_le_bytes()[0] & 0x7f;
+		value >>= 7;
+		if value == 0 {
+			out.push(byte);
+			return;
+		}
+		out.push(byte | 0x80);
+	}
+}
+
+fn leb_i32(out: &mut Vec<u8>, mut value: i32) {
+	loop {
+		let byte = value.to_le_bytes()[0] & 0x7f;
+		value >>= 7;
+		// done once the rest is all sign bits, and the sign bit of this byte matches them
+		if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+			out.push(byte);
+			return;
+		}
+		out.push(byte | 0x80);
+	}
+}
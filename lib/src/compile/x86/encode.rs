@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+
+use super::{Alu, Assembly, Cond, Inst, Label, Mem, Operand, Reg, Src, Width, DATA_ALIGN};
+
+/// Machine code and read-only data, waiting for the zeroed data to be placed.
+pub(in crate::compile) struct Encoded {
+	/// The code followed by the read-only data.
+	pub bytes: Vec<u8>,
+	/// The offset of the entry label in `bytes`, if there is one.
+	pub entry: Option<usize>,
+	/// The size of the zeroed data.
+	pub bss_len: u64,
+	/// The offsets in `bytes` of 32-bit displacements to the zeroed data, with the offset of their target in the zeroed data.
+	bss_refs: Vec<(usize, u64)>,
+}
+
+impl Encoded {
+	/// Resolve references to the zeroed data, placing it `bss_offset` bytes after the start of the code.
+	///
+	/// Returns `None` if a reference is too far away.
+	pub fn link(mut self, bss_offset: u64) -> Option<Vec<u8>> {
+		for &(at, target) in &self.bss_refs {
+			let next = u64::try_from(at + 4).ok()?;
+			let displacement =
+				i64::try_from(bss_offset.checked_add(target)?).ok()? - i64::try_from(next).ok()?;
+			let displacement = i32::try_from(displacement).ok()?;
+			self.bytes[at..at + 4].copy_from_slice(&displacement.to_le_bytes());
+		}
+		Some(self.bytes)
+	}
+}
+
+/// Encode `assembly` as machine code.
+///
+/// # Panics
+///
+/// Panics if a label is undefined or defined twice, or an immediate doesn't fit its instruction.
+pub(in crate::compile) fn encode(assembly: &Assembly) -> Encoded {
+	let mut encoder = Encoder::default();
+	for &inst in &assembly.text {
+		encoder.inst(inst);
+	}
+	for (name, bytes) in &assembly.rodata {
+		encoder.define(Label::Named(name));
+		encoder.code.extend(bytes);
+	}
+
+	let mut bss = HashMap::new();
+	let mut bss_len: u64 = 0;
+	for &(name, size) in &assembly.bss {
+		bss_len = bss_len.next_multiple_of(DATA_ALIGN);
+		bss.insert(Label::Named(name), bss_len);
+		bss_len += size;
+	}
+
+	let mut bss_refs = Vec::new();
+	for &(at, label) in &encoder.fixups {
+		if let Some(&target) = encoder.labels.get(&label) {
+			let displacement = i32::try_from(target)
+				.ok()
+				.zip(i32::try_from(at + 4).ok())
+				.map(|(target, next)| target - next)
+				.expect("code is less than 2 GiB");
+			encoder.code[at..at + 4].copy_from_slice(&displacement.to_le_bytes());
+		} else {
+			let target = bss
+				.get(&label)
+				.unwrap_or_else(|| panic!("undefined label {label}"));
+			bss_refs.push((at, *target));
+		}
+	}
+
+	Encoded {
+		entry: assembly
+			.entry
+			.map(|entry| encoder.labels[&Label::Named(entry)]),
+		bytes: encoder.code,
+		bss_len,
+		bss_refs,
+	}
+}
+
+#[derive(Default)]
+struct Encoder {
+	code: Vec<u8>,
+	labels: HashMap<Label, usize>,
+	/// The offsets of 32-bit displacements, relative to the end of the displacement, to labels.
+	fixups: Vec<(usize, Label)>,
+}
+
+impl Encoder {
+	fn define(&mut self, label: Label) {
+		let previous = self.labels.insert(label, self.code.len());
+		assert!(previous.is_none(), "label {label} is defined twice");
+	}
+
+	/// Push a 32-bit displacement to `label`.
+	fn displacement(&mut self, label: Label) {
+		self.fixups.push((self.code.len(), label));
+		self.code.extend([0; 4]);
+	}
+
+	fn inst(&mut self, inst: Inst) {
+		match inst {
+			Inst::Label(label) => self.define(label),
+			Inst::Alu(alu, width, Src::Imm(imm), dst) => {
+				let digit = match alu {
+					Alu::Add => 0,
+					Alu::Sub => 5,
+					Alu::Cmp => 7,
+					Alu::Xor => 6,
+				};
+				if width == Width::Byte {
+					self.modrm(width, &[0x80], digit, false, dst);
+					self.imm(width, imm);
+				} else if let Ok(imm) = i8::try_from(signed(width, imm)) {
+					// sign-extended from a byte
+					self.modrm(width, &[0x83], digit, false, dst);
+					self.code.extend(imm.to_le_bytes());
+				} else {
+					self.modrm(width, &[0x81], digit, false, dst);
+					self.imm(width, imm);
+				}
+			}
+			Inst::Alu(alu, width, Src::Reg(src), dst) => {
+				let opcode = match alu {
+					Alu::Add => 0x00,
+					Alu::Sub => 0x28,
+					Alu::Cmp => 0x38,
+					Alu::Xor => 0x30,
+				};
+				self.modrm(width, &[opcode + wide(width)], src.number(), true, dst);
+			}
+			Inst::Test(width, a, b) => {
+				self.modrm(
+					width,
+					&[0x84 + wide(width)],
+					b.number(),
+					true,
+					Operand::Reg(a),
+				);
+			}
+			Inst::Mov(width, Src::Imm(imm), dst) => {
+				self.modrm(width, &[0xc6 + wide(width)], 0, false, dst);
+				self.imm(width, imm);
+			}
+			Inst::Mov(width, Src::Reg(src), dst) => {
+				self.modrm(width, &[0x88 + wide(width)], src.number(), true, dst);
+			}
+			Inst::MovAbs(imm, reg) => {
+				self.code.push(0x48 | (reg.number() >> 3));
+				self.code.push(0xb8 + (reg.number() & 7));
+				self.code.extend(imm.to_le_bytes());
+			}
+			Inst::Load(width, mem, reg) => {
+				let (width, opcode): (_, &[u8]) = match width {
+					Width::Byte => (Width::Long, &[0x0f, 0xb6]),
+					Width::Word => (Width::Long, &[0x0f, 0xb7]),
+					Width::Long | Width::Quad => (width, &[0x8b]),
+				};
+				self.modrm(width, opcode, reg.number(), false, Operand::Mem(mem));
+			}
+			Inst::Lea(label, reg) => {
+				// relative to the next instruction
+				self.code.push(0x48 | ((reg.number() >> 3) << 2));
+				self.code.push(0x8d);
+				self.code.push(((reg.number() & 7) << 3) | 0b101);
+				self.displacement(label);
+			}
+			Inst::Imul(imm, reg) => {
+				self.modrm(Width::Long, &[0x69], reg.number(), false, Operand::Reg(reg));
+				self.code.extend(imm.to_le_bytes());
+			}
+			Inst::Jmp(label) => {
+				self.code.push(0xe9);
+				self.displacement(label);
+			}
+			Inst::Jcc(cond, label) => {
+				let code = match cond {
+					Cond::B => 0x2,
+					Cond::Ae => 0x3,
+					Cond::E => 0x4,
+					Cond::Ne => 0x5,
+					Cond::S => 0x8,
+					Cond::Le => 0xe,
+				};
+				self.code.extend([0x0f, 0x80 + code]);
+				self.displacement(label);
+			}
+			Inst::Call(label) => {
+				self.code.push(0xe8);
+				self.displacement(label);
+			}
+			Inst::CallReg(reg) => self.modrm(Width::Long, &[0xff], 2, false, Operand::Reg(reg)),
+			Inst::Push(reg) => self.short(0x50, reg),
+			Inst::Pop(reg) => self.short(0x58, reg),
+			Inst::Ret => self.code.push(0xc3),
+			Inst::Syscall => self.code.extend([0x0f, 0x05]),
+		}
+	}
+
+	/// Push an instruction that has the register in the low bits of its opcode.
+	fn short(&mut self, opcode: u8, reg: Reg) {
+		if reg.number() >= 8 {
+			self.code.push(0x41);
+		}
+		self.code.push(opcode + (reg.number() & 7));
+	}
+
+	/// Push an instruction with a `ModRM` byte, and a prefix for its width and extended registers.
+	///
+	/// `reg` is the register number or opcode extension in the `ModRM` byte, and `reg_is_register` is whether it's a register of the instruction's width.
+	fn modrm(&mut self, width: Width, opcode: &[u8], reg: u8, reg_is_register: bool, rm: Operand) {
+		// without a REX prefix, byte registers 4 to 7 are ah, ch, dh and bh instead of spl, bpl, sil and dil
+		let uniform_byte = |number: u8| width == Width::Byte && (4..8).contains(&number);
+		let (x, b, force_rex) = match rm {
+			Operand::Reg(rm) => (0, rm.number() >> 3, uniform_byte(rm.number())),
+			Operand::Mem(mem) => (
+				mem.index.map_or(0, |index| index.number() >> 3),
+				mem.base.number() >> 3,
+				false,
+			),
+		};
+		let w = u8::from(width == Width::Quad);
+		let r = reg >> 3;
+		let force_rex = force_rex || (reg_is_register && uniform_byte(reg));
+
+		if width == Width::Word {
+			self.code.push(0x66);
+		}
+		if w | r | x | b != 0 || force_rex {
+			self.code.push(0x40 | (w << 3) | (r << 2) | (x << 1) | b);
+		}
+		self.code.extend(opcode);
+
+		let reg = (reg & 7) << 3;
+		match rm {
+			Operand::Reg(rm) => self.code.push(0b11_000_000 | reg | (rm.number() & 7)),
+			Operand::Mem(mem) => self.mem(reg, mem),
+		}
+	}
+
+	/// Push the `ModRM` byte for a memory operand and what follows it.
+	fn mem(&mut self, reg: u8, mem: Mem) {
+		let base = mem.base.number() & 7;
+		// rbp and r13 need a displacement, since without one the encoding means something else
+		let (mode, disp) = if mem.disp == 0 && base != 0b101 {
+			(0b00, Vec::new())
+		} else if let Ok(disp) = i8::try_from(mem.disp) {
+			(0b01, disp.to_le_bytes().to_vec())
+		} else {
+			(0b10, mem.disp.to_le_bytes().to_vec())
+		};
+
+		// rsp and r12 as the base need a SIB byte
+		if mem.index.is_some() || base == 0b100 {
+			self.code.push((mode << 6) | reg | 0b100);
+			let index = mem.index.map_or(0b100, |index| {
+				assert_ne!(index, Reg::Rsp, "rsp can't be an index");
+				index.number() & 7
+			});
+			self.code.push((index << 3) | base);
+		} else {
+			self.code.push((mode << 6) | reg | base);
+		}
+		self.code.extend(disp);
+	}
+
+	/// Push an immediate of the instruction's width, which is 32 bits for quad words.
+	fn imm(&mut self, width: Width, imm: i64) {
+		let (bits, len) = match width {
+			Width::Byte => (8, 1),
+			Width::Word => (16, 2),
+			Width::Long | Width::Quad => (32, 4),
+		};
+		let fits = if width == Width::Quad {
+			i32::try_from(imm).is_ok()
+		} else {
+			(-(1 << (bits - 1))..1 << bits).contains(&imm)
+		};
+		assert!(fits, "immediate {imm} doesn't fit in {bits} bits");
+		self.code.extend(&imm.to_le_bytes()[..len]);
+	}
+}
+
+/// Add to an opcode for instructions that aren't on bytes.
+fn wide(width: Width) -> u8 {
+	u8::from(width != Width::Byte)
+}
+
+/// Interpret the low bits of `imm` for the width as signed.
+fn signed(width: Width, imm: i64) -> i64 {
+	let bytes = imm.to_le_bytes();
+	match width {
+		Width::Byte => i8::from_le_bytes([bytes[0]]).into(),
+		Width::Word => i16::from_le_bytes([bytes[0], bytes[1]]).into(),
+		Width::Long => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]).into(),
+		Width::Quad => imm,
+	}
+}
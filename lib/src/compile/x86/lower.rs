@@ -0,0 +1,540 @@
+use super::{Alu, Assembly, Cond, Inst, Label, Mem, Operand, Reg, Src, Width};
+use crate::cell_type::CellType;
+use crate::compile::{InstructionStream, PointerRange, RenderAsmOptions};
+use crate::instruction::Instruction;
+
+/// Reads a byte into eax, or 0 at the end of the input, preserving rbx, rbp and r12 to r15.
+pub(in crate::compile) const READ: Label = Label::Named("bf_read");
+/// Writes the byte in al, preserving rbx, rbp and r12 to r15.
+pub(in crate::compile) const WRITE: Label = Label::Named("bf_write");
+/// Where the code jumps when the pointer moves past the end of the data array.
+pub(in crate::compile) const OVERFLOW: Label = Label::Named("bf_overflow");
+/// Where the code jumps when the pointer moves before the start of the data array.
+pub(in crate::compile) const UNDERFLOW: Label = Label::Named("bf_underflow");
+/// Where resumable code jumps when reading or writing fails.
+pub(in crate::compile) const IO_ERROR: Label = Label::Named("bf_io_error");
+
+/// Lowers instructions to x86-64 code.
+///
+/// The cells are at r14 + rbx, and r15 is the length of the data array in bytes, all of which are preserved.
+/// The code calls [`READ`] and [`WRITE`], and if bounds are checked, jumps to [`OVERFLOW`] or [`UNDERFLOW`].
+/// It clobbers rax, rcx and rbp, and uses the local labels from 0 to [`next_label`](Self::next_label).
+pub(in crate::compile) struct Lower<T> {
+	pub text: Vec<Inst>,
+	/// The code that failed checks jump to in resumable code, which must be placed where it isn't run otherwise.
+	pub resume: Vec<Inst>,
+	bounds_checks: bool,
+	resumable: bool,
+	/// The index of the instruction being lowered.
+	index: usize,
+	next_label: usize,
+	cell: std::marker::PhantomData<T>,
+}
+
+impl<T: CellType> Lower<T> {
+	const WIDTH: Width = match T::BYTES {
+		1 => Width::Byte,
+		2 => Width::Word,
+		_ => Width::Long,
+	};
+
+	const CELL: Mem = Mem {
+		base: Reg::R14,
+		index: Some(Reg::Rbx),
+		disp: 0,
+	};
+
+	pub fn new(instructions: &[Instruction<T>], bounds_checks: bool) -> Self {
+		Self::with_checks(instructions, bounds_checks, false)
+	}
+
+	/// Lower instructions to code that the interpreter can take over from when it fails.
+	///
+	/// Bounds are always checked, and [`READ`] and [`WRITE`] must return a negative eax when they fail.
+	/// When a bounds check fails, the code jumps to [`OVERFLOW`] or [`UNDERFLOW`] with the pointer as it was before the instruction, so running the instruction again fails the same way.
+	/// When reading or writing fails, it jumps to [`IO_ERROR`].
+	/// Either way, rcx is the index of the instruction.
+	#[cfg(all(feature = "jit", target_os = "linux", target_arch = "x86_64"))]
+	pub fn resumable(instructions: &[Instruction<T>]) -> Self {
+		Self::with_checks(instructions, true, true)
+	}
+
+	fn with_checks(instructions: &[Instruction<T>], bounds_checks: bool, resumable: bool) -> Self {
+		let mut lower = Self {
+			text: Vec::new(),
+			resume: Vec::new(),
+			bounds_checks,
+			resumable,
+			index: 0,
+			// loops are labelled with the index of their start and end
+			next_label: instructions.len(),
+			cell: std::marker::PhantomData,
+		};
+		for (idx, &instruction) in instructions.iter().enumerate() {
+			lower.index = idx;
+			lower.instruction(idx, instruction);
+		}
+		lower
+	}
+
+	/// The first local label that isn't used.
+	pub fn next_label(&self) -> usize {
+		self.next_label
+	}
+
+	fn label(&mut self) -> Label {
+		self.next_label += 1;
+		Label::Local(self.next_label - 1)
+	}
+
+	fn push(&mut self, inst: Inst) {
+		self.text.push(inst);
+	}
+
+	fn instruction(&mut self, idx: usize, instruction: Instruction<T>) {
+		let width = Self::WIDTH;
+		let cell = |value: T| -> i64 { i64::from(value.into()) };
+		let bytes = |cells: u32| u64::from(cells) * u64::from(T::BYTES);
+		let here = Operand::Mem(Self::CELL);
+
+		match instruction {
+			Instruction::Set(value) => self.push(Inst::Mov(width, Src::Imm(cell(value)), here)),
+			Instruction::Inc(amount) => {
+				self.push(Inst::Alu(
+					Alu::Add,
+					width,
+					Src::Imm(cell(amount.into())),
+					here,
+				));
+			}
+			Instruction::Dec(amount) => {
+				self.push(Inst::Alu(
+					Alu::Sub,
+					width,
+					Src::Imm(cell(amount.into())),
+					here,
+				));
+			}
+			Instruction::IncPtr(amount) => {
+				let bytes = bytes(amount.get());
+				self.right(bytes, offset(Alu::Sub, bytes, Reg::Rbx));
+			}
+			Instruction::DecPtr(amount) => {
+				let bytes = bytes(amount.get());
+				self.left(bytes, offset(Alu::Add, bytes, Reg::Rbx));
+			}
+			Instruction::Write => self.write(Self::CELL),
+			Instruction::Read => self.read(Self::CELL),
+			Instruction::LoopStart(end) => {
+				self.push(Inst::Alu(Alu::Cmp, width, Src::Imm(0), here));
+				self.push(Inst::Jcc(Cond::E, Label::Local(end as usize)));
+				self.push(Inst::Label(Label::Local(idx)));
+			}
+			Instruction::LoopEnd(start) => {
+				self.push(Inst::Alu(Alu::Cmp, width, Src::Imm(0), here));
+				self.push(Inst::Jcc(Cond::Ne, Label::Local(start as usize)));
+				self.push(Inst::Label(Label::Local(idx)));
+			}
+			Instruction::MulAdd(offset, factor) => {
+				// the target is only accessed, and so only checked, if the current cell is nonzero
+				let skip = self.label();
+				self.push(Inst::Load(width, Self::CELL, Reg::Rcx));
+				self.push(Inst::Test(Width::Long, Reg::Rcx, Reg::Rcx));
+				self.push(Inst::Jcc(Cond::E, skip));
+				let target = self.at(offset);
+				// only the low bits of the product are kept, which are the same for the signed factor
+				let factor = i32::from_ne_bytes(Into::<T>::into(factor).into().to_ne_bytes());
+				self.push(Inst::Imul(factor, Reg::Rcx));
+				self.push(Inst::Alu(
+					Alu::Add,
+					width,
+					Src::Reg(Reg::Rcx),
+					Operand::Mem(target),
+				));
+				self.push(Inst::Label(skip));
+			}
+			Instruction::ScanRight(stride) => {
+				self.scan(|lower, restore| lower.right(bytes(stride.get()), restore));
+			}
+			Instruction::ScanLeft(stride) => {
+				self.scan(|lower, restore| lower.left(bytes(stride.get()), restore));
+			}
+			Instruction::SetAt(offset, value) => {
+				let target = self.at(offset);
+				self.push(Inst::Mov(
+					width,
+					Src::Imm(cell(value)),
+					Operand::Mem(target),
+				));
+			}
+			Instruction::AddAt(offset, amount) => {
+				let target = self.at(offset);
+				self.push(Inst::Alu(
+					Alu::Add,
+					width,
+					Src::Imm(cell(amount.into())),
+					Operand::Mem(target),
+				));
+			}
+			Instruction::ReadAt(offset) => {
+				let target = self.at(offset);
+				self.read(target);
+			}
+			Instruction::WriteAt(offset) => {
+				let target = self.at(offset);
+				self.write(target);
+			}
+			Instruction::WriteConst(byte) => {
+				self.push(Inst::Mov(
+					Width::Long,
+					Src::Imm(byte.into()),
+					Operand::Reg(Reg::Rax),
+				));
+				self.call(WRITE);
+			}
+		}
+	}
+
+	/// Move the pointer with `step` until the current cell is zero.
+	///
+	/// `step` is given the code that restores the pointer to where the scan started.
+	fn scan(&mut self, step: impl FnOnce(&mut Self, Vec<Inst>)) {
+		let top = self.label();
+		let done = self.label();
+		if self.resumable {
+			self.push(mov_reg(Reg::Rbx, Reg::Rbp));
+		}
+		self.push(Inst::Label(top));
+		self.push(Inst::Alu(
+			Alu::Cmp,
+			Self::WIDTH,
+			Src::Imm(0),
+			Operand::Mem(Self::CELL),
+		));
+		self.push(Inst::Jcc(Cond::E, done));
+		step(self, vec![mov_reg(Reg::Rbp, Reg::Rbx)]);
+		self.push(Inst::Jmp(top));
+		self.push(Inst::Label(done));
+	}
+
+	/// Jump to `target` if `cond` holds, going through code that runs `restore` and sets rcx to the index of the instruction first if the code is resumable.
+	fn fail(&mut self, cond: Cond, target: Label, restore: Vec<Inst>) {
+		if self.resumable {
+			let label = self.label();
+			self.push(Inst::Jcc(cond, label));
+			self.resume.push(Inst::Label(label));
+			self.resume.extend(restore);
+			self
+				.resume
+				.extend([Inst::MovAbs(self.index as u64, Reg::Rcx), Inst::Jmp(target)]);
+		} else {
+			self.push(Inst::Jcc(cond, target));
+		}
+	}
+
+	/// Jump to [`OVERFLOW`] if `reg` is past the end of the data array, when checking bounds.
+	fn check_end(&mut self, reg: Reg, restore: Vec<Inst>) {
+		if self.bounds_checks {
+			self.push(Inst::Alu(
+				Alu::Cmp,
+				Width::Quad,
+				Src::Reg(Reg::R15),
+				Operand::Reg(reg),
+			));
+			self.fail(Cond::Ae, OVERFLOW, restore);
+		}
+	}
+
+	/// Jump to [`UNDERFLOW`] if the last subtraction borrowed, which is iff it went below zero, when checking bounds.
+	fn check_start(&mut self, restore: Vec<Inst>) {
+		if self.bounds_checks {
+			self.fail(Cond::B, UNDERFLOW, restore);
+		}
+	}
+
+	/// Move the pointer right by `bytes`, with the code that undoes the move if it fails.
+	fn right(&mut self, bytes: u64, restore: Vec<Inst>) {
+		self.text.extend(offset(Alu::Add, bytes, Reg::Rbx));
+		self.check_end(Reg::Rbx, restore);
+	}
+
+	/// Move the pointer left by `bytes`, with the code that undoes the move if it fails.
+	fn left(&mut self, bytes: u64, restore: Vec<Inst>) {
+		self.text.extend(offset(Alu::Sub, bytes, Reg::Rbx));
+		self.check_start(restore);
+	}
+
+	/// Get the cell at `offset` from the pointer, computing its position into rbp first if needed.
+	fn at(&mut self, offset: i32) -> Mem {
+		let disp = i64::from(offset) * i64::from(T::BYTES);
+		if !self.bounds_checks {
+			if let Ok(disp) = i32::try_from(disp) {
+				return Mem { disp, ..Self::CELL };
+			}
+		}
+
+		self.push(Inst::Mov(
+			Width::Quad,
+			Src::Reg(Reg::Rbx),
+			Operand::Reg(Reg::Rbp),
+		));
+		// the pointer doesn't move, so there's nothing to restore
+		if offset < 0 {
+			self
+				.text
+				.extend(self::offset(Alu::Sub, disp.unsigned_abs(), Reg::Rbp));
+			self.check_start(Vec::new());
+		} else {
+			self
+				.text
+				.extend(self::offset(Alu::Add, disp.unsigned_abs(), Reg::Rbp));
+			self.check_end(Reg::Rbp, Vec::new());
+		}
+		Mem {
+			index: Some(Reg::Rbp),
+			..Self::CELL
+		}
+	}
+
+	/// Call [`READ`] or [`WRITE`], checking that it succeeded if the code is resumable.
+	fn call(&mut self, label: Label) {
+		self.push(Inst::Call(label));
+		if self.resumable {
+			self.push(Inst::Test(Width::Long, Reg::Rax, Reg::Rax));
+			self.fail(Cond::S, IO_ERROR, Vec::new());
+		}
+	}
+
+	fn read(&mut self, target: Mem) {
+		self.call(READ);
+		self.push(Inst::Mov(
+			Self::WIDTH,
+			Src::Reg(Reg::Rax),
+			Operand::Mem(target),
+		));
+	}
+
+	fn write(&mut self, target: Mem) {
+		// only the low byte is written, which truncates the cell
+		self.push(Inst::Load(Width::Byte, target, Reg::Rax));
+		self.call(WRITE);
+	}
+}
+
+/// Add or subtract `bytes` to or from `reg`, with `alu`.
+fn offset(alu: Alu, bytes: u64, reg: Reg) -> Vec<Inst> {
+	if let Ok(bytes) = i32::try_from(bytes) {
+		vec![Inst::Alu(
+			alu,
+			Width::Quad,
+			Src::Imm(bytes.into()),
+			Operand::Reg(reg),
+		)]
+	} else {
+		vec![
+			Inst::MovAbs(bytes, Reg::Rax),
+			Inst::Alu(alu, Width::Quad, Src::Reg(Reg::Rax), Operand::Reg(reg)),
+		]
+	}
+}
+
+/// The size of the output buffer, which is written when full, before reading, and before exiting.
+const OUTPUT_LEN: i64 = 4096;
+
+const TAPE: Label = Label::Named("bf_tape");
+const OUTPUT: Label = Label::Named("bf_output");
+const INPUT: Label = Label::Named("bf_input");
+const FLUSH: Label = Label::Named("bf_flush");
+const INPUT_ERROR: Label = Label::Named("bf_input_error");
+const OUTPUT_ERROR: Label = Label::Named("bf_output_error");
+const FAIL: Label = Label::Named("bf_fail");
+
+const SYS_READ: i64 = 0;
+const SYS_WRITE: i64 = 1;
+const SYS_EXIT: i64 = 60;
+
+/// A standalone Linux program that makes syscalls instead of using libc.
+///
+/// Errors are printed to stderr with the messages of [`interpret::Error`](crate::interpret::Error), and exit with the statuses in [`RenderAsmOptions`].
+pub(in crate::compile) fn linux<T: CellType>(
+	stream: &InstructionStream<T>,
+	bounds_checks: bool,
+) -> Assembly {
+	// without bounds checks, make room for cells left of the start like when rendering C
+	let start = match stream.pointer_range() {
+		PointerRange::Bounded { min, .. } if !bounds_checks => min.unsigned_abs(),
+		_ => 0,
+	};
+	let tape_bytes = (stream.recommended_array_size() as u64)
+		.saturating_add(start)
+		.saturating_mul(T::BYTES.into());
+
+	let lower = Lower::new(stream.instructions(), bounds_checks);
+	let next_label = lower.next_label();
+	let mut text = vec![
+		Inst::Label(Label::Named("_start")),
+		// the cells are at r14 + rbx, and r12 bytes of output are buffered
+		Inst::Lea(TAPE, Reg::R14),
+		Inst::MovAbs(start * u64::from(T::BYTES), Reg::Rbx),
+		Inst::MovAbs(tape_bytes, Reg::R15),
+		xor(Reg::R12),
+	];
+	text.extend(lower.text);
+	text.extend([
+		Inst::Call(FLUSH),
+		mov_imm(SYS_EXIT, Reg::Rax),
+		xor(Reg::Rdi),
+		Inst::Syscall,
+	]);
+
+	let mut assembly = Assembly {
+		text,
+		rodata: Vec::new(),
+		bss: vec![
+			("bf_tape", tape_bytes),
+			("bf_output", OUTPUT_LEN.unsigned_abs()),
+			("bf_input", 1),
+		],
+		entry: Some("_start"),
+	};
+	runtime(&mut assembly, next_label);
+	assembly
+}
+
+fn xor(reg: Reg) -> Inst {
+	Inst::Alu(Alu::Xor, Width::Long, Src::Reg(reg), Operand::Reg(reg))
+}
+
+fn mov_imm(imm: i64, reg: Reg) -> Inst {
+	Inst::Mov(Width::Long, Src::Imm(imm), Operand::Reg(reg))
+}
+
+fn mov_reg(src: Reg, dst: Reg) -> Inst {
+	Inst::Mov(Width::Quad, Src::Reg(src), Operand::Reg(dst))
+}
+
+/// Add the functions and data that the lowered code uses, which preserve every register except rax, rcx, rdx, rsi, rdi and r11.
+fn runtime(assembly: &mut Assembly, first_label: usize) {
+	let local = |id| Label::Local(first_label + id);
+	let reg = Operand::Reg;
+
+	assembly.text.extend([
+		// buffer the byte in al, writing the buffer when it's full
+		Inst::Label(WRITE),
+		Inst::Lea(OUTPUT, Reg::Rcx),
+		Inst::Mov(
+			Width::Byte,
+			Src::Reg(Reg::Rax),
+			Operand::Mem(Mem {
+				base: Reg::Rcx,
+				index: Some(Reg::R12),
+				disp: 0,
+			}),
+		),
+		Inst::Alu(Alu::Add, Width::Quad, Src::Imm(1), reg(Reg::R12)),
+		Inst::Alu(Alu::Cmp, Width::Quad, Src::Imm(OUTPUT_LEN), reg(Reg::R12)),
+		Inst::Jcc(Cond::E, FLUSH),
+		Inst::Ret,
+		// write the buffered output
+		Inst::Label(FLUSH),
+		Inst::Lea(OUTPUT, Reg::Rsi),
+		mov_reg(Reg::R12, Reg::Rdx),
+		Inst::Label(local(0)),
+		Inst::Test(Width::Quad, Reg::Rdx, Reg::Rdx),
+		Inst::Jcc(Cond::E, local(1)),
+		mov_imm(SYS_WRITE, Reg::Rax),
+		mov_imm(1, Reg::Rdi),
+		Inst::Syscall,
+		Inst::Test(Width::Quad, Reg::Rax, Reg::Rax),
+		Inst::Jcc(Cond::Le, OUTPUT_ERROR),
+		Inst::Alu(Alu::Add, Width::Quad, Src::Reg(Reg::Rax), reg(Reg::Rsi)),
+		Inst::Alu(Alu::Sub, Width::Quad, Src::Reg(Reg::Rax), reg(Reg::Rdx)),
+		Inst::Jmp(local(0)),
+		Inst::Label(local(1)),
+		xor(Reg::R12),
+		Inst::Ret,
+		// read a byte into eax, or 0 at the end of the input
+		Inst::Label(READ),
+		Inst::Call(FLUSH),
+		mov_imm(SYS_READ, Reg::Rax),
+		xor(Reg::Rdi),
+		Inst::Lea(INPUT, Reg::Rsi),
+		mov_imm(1, Reg::Rdx),
+		Inst::Syscall,
+		Inst::Test(Width::Quad, Reg::Rax, Reg::Rax),
+		Inst::Jcc(Cond::S, INPUT_ERROR),
+		Inst::Jcc(Cond::E, local(2)),
+		Inst::Load(
+			Width::Byte,
+			Mem {
+				base: Reg::Rsi,
+				index: None,
+				disp: 0,
+			},
+			Reg::Rax,
+		),
+		Inst::Label(local(2)),
+		Inst::Ret,
+	]);
+
+	errors(assembly);
+}
+
+/// Add the code that prints an error message and exits, and the messages.
+fn errors(assembly: &mut Assembly) {
+	let errors = [
+		(
+			OVERFLOW,
+			RenderAsmOptions::OVERFLOW_STATUS,
+			"bf_overflow_message",
+			"runtime overflowed its data array",
+		),
+		(
+			UNDERFLOW,
+			RenderAsmOptions::UNDERFLOW_STATUS,
+			"bf_underflow_message",
+			"runtime underflowed its data array",
+		),
+		(
+			INPUT_ERROR,
+			RenderAsmOptions::IO_ERROR_STATUS,
+			"bf_input_error_message",
+			"IO error while reading from input",
+		),
+		(
+			OUTPUT_ERROR,
+			RenderAsmOptions::IO_ERROR_STATUS,
+			"bf_output_error_message",
+			"IO error while writing to output",
+		),
+	];
+	for (label, status, message_label, message) in errors {
+		let message = format!("error: {message}\n").into_bytes();
+		assembly.text.push(Inst::Label(label));
+		if label == OUTPUT_ERROR {
+			// drop the output that couldn't be written so the flush below doesn't fail again
+			assembly.text.push(xor(Reg::R12));
+		}
+		assembly.text.extend([
+			mov_imm(status.into(), Reg::Rbx),
+			Inst::Lea(Label::Named(message_label), Reg::Rbp),
+			mov_imm(message.len().try_into().unwrap(), Reg::R13),
+			Inst::Jmp(FAIL),
+		]);
+		assembly.rodata.push((message_label, message));
+	}
+
+	assembly.text.extend([
+		// write the output so far, then the message at rbp with length r13 to stderr, and exit with the status in ebx
+		Inst::Label(FAIL),
+		Inst::Call(FLUSH),
+		mov_imm(SYS_WRITE, Reg::Rax),
+		mov_imm(2, Reg::Rdi),
+		mov_reg(Reg::Rbp, Reg::Rsi),
+		mov_reg(Reg::R13, Reg::Rdx),
+		Inst::Syscall,
+		mov_imm(SYS_EXIT, Reg::Rax),
+		Inst::Mov(Width::Long, Src::Reg(Reg::Rbx), Operand::Reg(Reg::Rdi)),
+		Inst::Syscall,
+	]);
+}
@@ -0,0 +1,341 @@
+//! x86-64 code generation, shared by the backends that produce it.
+//!
+//! Instructions are lowered to [`Inst`]s, which are printed as GAS assembly or encoded as machine code.
+
+use std::fmt;
+
+mod encode;
+mod lower;
+
+pub(super) use encode::encode;
+pub(super) use lower::linux;
+#[cfg(all(feature = "jit", target_os = "linux", target_arch = "x86_64"))]
+pub(super) use lower::{Lower, IO_ERROR, OVERFLOW, READ, UNDERFLOW, WRITE};
+
+/// A general purpose register.
+#[allow(dead_code)] // every register has a number, even unused ones
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(super) enum Reg {
+	Rax,
+	Rcx,
+	Rdx,
+	Rbx,
+	Rsp,
+	Rbp,
+	Rsi,
+	Rdi,
+	R8,
+	R9,
+	R10,
+	R11,
+	R12,
+	R13,
+	R14,
+	R15,
+}
+
+impl Reg {
+	/// The number of the register in encodings.
+	fn number(self) -> u8 {
+		self as u8
+	}
+
+	fn name(self, width: Width) -> String {
+		const LEGACY: [&str; 8] = ["ax", "cx", "dx", "bx", "sp", "bp", "si", "di"];
+
+		let number = usize::from(self.number());
+		if let Some(&legacy) = LEGACY.get(number) {
+			match width {
+				Width::Byte if number < 4 => format!("{}l", &legacy[..1]),
+				Width::Byte => format!("{legacy}l"),
+				Width::Word => legacy.to_owned(),
+				Width::Long => format!("e{legacy}"),
+				Width::Quad => format!("r{legacy}"),
+			}
+		} else {
+			let suffix = match width {
+				Width::Byte => "b",
+				Width::Word => "w",
+				Width::Long => "d",
+				Width::Quad => "",
+			};
+			format!("r{number}{suffix}")
+		}
+	}
+}
+
+/// The size of an operand.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(super) enum Width {
+	Byte,
+	Word,
+	Long,
+	Quad,
+}
+
+impl Width {
+	fn suffix(self) -> char {
+		match self {
+			Self::Byte => 'b',
+			Self::Word => 'w',
+			Self::Long => 'l',
+			Self::Quad => 'q',
+		}
+	}
+}
+
+/// A memory operand at `base + index + disp`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(super) struct Mem {
+	pub base: Reg,
+	pub index: Option<Reg>,
+	pub disp: i32,
+}
+
+/// The destination of an instruction.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(super) enum Operand {
+	Reg(Reg),
+	Mem(Mem),
+}
+
+/// The source of an instruction.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(super) enum Src {
+	/// An immediate, which must fit in the width of the instruction as a signed or unsigned number, or in 32 bits sign-extended for quad words.
+	Imm(i64),
+	Reg(Reg),
+}
+
+/// An arithmetic instruction that takes a source and a destination.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(super) enum Alu {
+	Add,
+	Sub,
+	Cmp,
+	Xor,
+}
+
+/// A condition for a conditional jump.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(super) enum Cond {
+	/// Unsigned less than, or borrowed.
+	B,
+	/// Unsigned greater than or equal to.
+	Ae,
+	E,
+	Ne,
+	/// Negative.
+	S,
+	/// Signed less than or equal to.
+	Le,
+}
+
+/// A jump target or a piece of data.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub(super) enum Label {
+	Named(&'static str),
+	/// A label that is local to the code it's in, like loops.
+	Local(usize),
+}
+
+/// An instruction, in AT&T operand order.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(super) enum Inst {
+	Label(Label),
+	Alu(Alu, Width, Src, Operand),
+	/// Set flags from the bitwise and of two registers.
+	Test(Width, Reg, Reg),
+	Mov(Width, Src, Operand),
+	/// Move a 64-bit immediate into a register.
+	MovAbs(u64, Reg),
+	/// Load from memory, zero-extending into the 32-bit register if the width is smaller.
+	Load(Width, Mem, Reg),
+	/// Load the address of a label.
+	Lea(Label, Reg),
+	/// Multiply the 32-bit register by an immediate.
+	Imul(i32, Reg),
+	Jmp(Label),
+	Jcc(Cond, Label),
+	Call(Label),
+	/// Call the address in a register.
+	#[cfg_attr(
+		not(all(feature = "jit", target_os = "linux", target_arch = "x86_64")),
+		allow(dead_code)
+	)] // only the JIT calls into Rust
+	CallReg(Reg),
+	#[cfg_attr(
+		not(all(feature = "jit", target_os = "linux", target_arch = "x86_64")),
+		allow(dead_code)
+	)] // only the JIT saves registers
+	Push(Reg),
+	#[cfg_attr(
+		not(all(feature = "jit", target_os = "linux", target_arch = "x86_64")),
+		allow(dead_code)
+	)] // only the JIT saves registers
+	Pop(Reg),
+	Ret,
+	Syscall,
+}
+
+/// Code and the data it uses.
+#[derive(Default)]
+pub(super) struct Assembly {
+	pub text: Vec<Inst>,
+	/// Read-only data, each with its label.
+	pub rodata: Vec<(&'static str, Vec<u8>)>,
+	/// Zeroed data, each with its label and size in bytes.
+	pub bss: Vec<(&'static str, u64)>,
+	/// The label that execution starts at, if any.
+	pub entry: Option<&'static str>,
+}
+
+/// The alignment of each piece of data.
+const DATA_ALIGN: u64 = 16;
+
+impl fmt::Display for Label {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Named(name) => f.write_str(name),
+			Self::Local(id) => write!(f, ".L{id}"),
+		}
+	}
+}
+
+impl Mem {
+	fn display(self) -> String {
+		let disp = if self.disp == 0 {
+			String::new()
+		} else {
+			self.disp.to_string()
+		};
+		let base = self.base.name(Width::Quad);
+		match self.index {
+			Some(index) => format!("{disp}(%{base},%{})", index.name(Width::Quad)),
+			None => format!("{disp}(%{base})"),
+		}
+	}
+}
+
+impl Src {
+	fn display(self, width: Width) -> String {
+		match self {
+			Self::Imm(imm) => format!("${imm}"),
+			Self::Reg(reg) => format!("%{}", reg.name(width)),
+		}
+	}
+}
+
+impl Operand {
+	fn display(self, width: Width) -> String {
+		match self {
+			Self::Reg(reg) => format!("%{}", reg.name(width)),
+			Self::Mem(mem) => mem.display(),
+		}
+	}
+}
+
+impl fmt::Display for Inst {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match *self {
+			Self::Label(label) => write!(f, "{label}:"),
+			Self::Alu(alu, width, src, dst) => {
+				let name = match alu {
+					Alu::Add => "add",
+					Alu::Sub => "sub",
+					Alu::Cmp => "cmp",
+					Alu::Xor => "xor",
+				};
+				write!(
+					f,
+					"\t{name}{} {}, {}",
+					width.suffix(),
+					src.display(width),
+					dst.display(width)
+				)
+			}
+			Self::Test(width, a, b) => write!(
+				f,
+				"\ttest{} %{}, %{}",
+				width.suffix(),
+				a.name(width),
+				b.name(width)
+			),
+			Self::Mov(width, src, dst) => write!(
+				f,
+				"\tmov{} {}, {}",
+				width.suffix(),
+				src.display(width),
+				dst.display(width)
+			),
+			Self::MovAbs(imm, reg) => write!(f, "\tmovabsq ${imm}, %{}", reg.name(Width::Quad)),
+			Self::Load(width, mem, reg) => {
+				let (name, reg_width) = match width {
+					Width::Byte => ("movzbl", Width::Long),
+					Width::Word => ("movzwl", Width::Long),
+					Width::Long => ("movl", Width::Long),
+					Width::Quad => ("movq", Width::Quad),
+				};
+				write!(f, "\t{name} {}, %{}", mem.display(), reg.name(reg_width))
+			}
+			Self::Lea(label, reg) => write!(f, "\tleaq {label}(%rip), %{}", reg.name(Width::Quad)),
+			Self::Imul(imm, reg) => {
+				let reg = reg.name(Width::Long);
+				write!(f, "\timull ${imm}, %{reg}, %{reg}")
+			}
+			Self::Jmp(label) => write!(f, "\tjmp {label}"),
+			Self::Jcc(cond, label) => {
+				let name = match cond {
+					Cond::B => "jb",
+					Cond::Ae => "jae",
+					Cond::E => "je",
+					Cond::Ne => "jne",
+					Cond::S => "js",
+					Cond::Le => "jle",
+				};
+				write!(f, "\t{name} {label}")
+			}
+			Self::Call(label) => write!(f, "\tcall {label}"),
+			Self::CallReg(reg) => write!(f, "\tcall *%{}", reg.name(Width::Quad)),
+			Self::Push(reg) => write!(f, "\tpushq %{}", reg.name(Width::Quad)),
+			Self::Pop(reg) => write!(f, "\tpopq %{}", reg.name(Width::Quad)),
+			Self::Ret => f.write_str("\tret"),
+			Self::Syscall => f.write_str("\tsyscall"),
+		}
+	}
+}
+
+impl fmt::Display for Assembly {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(f, "\t.text")?;
+		if let Some(entry) = self.entry {
+			writeln!(f, "\t.globl {entry}")?;
+		}
+		for inst in &self.text {
+			writeln!(f, "{inst}")?;
+		}
+
+		if !self.rodata.is_empty() {
+			writeln!(f, "\n\t.section .rodata")?;
+			for (name, bytes) in &self.rodata {
+				let escaped: Vec<u8> = bytes
+					.iter()
+					.flat_map(|&byte| std::ascii::escape_default(byte))
+					.collect();
+				writeln!(
+					f,
+					"{name}:\n\t.ascii \"{}\"",
+					String::from_utf8_lossy(&escaped)
+				)?;
+			}
+		}
+
+		if !self.bss.is_empty() {
+			writeln!(f, "\n\t.bss")?;
+			for (name, size) in &self.bss {
+				writeln!(f, "\t.balign {DATA_ALIGN}\n{name}:\n\t.skip {size}")?;
+			}
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,320 @@
+//! Formats Brainfuck source code without changing what it does.
+//!
+//! The formatter indents loops by nesting depth, keeps short loops like `[->+<]` inline, removes whitespace between instructions, wraps long lines of code, and keeps comments and the line breaks between code.
+//! Formatting formatted code doesn't change it.
+
+use crate::compile::Error;
+use crate::span::{Position, Span};
+
+/// What a [`Token`] contains.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum TokenKind {
+	/// A run of instructions other than loop starts and ends.
+	Instructions,
+	/// A loop start, `[`.
+	LoopStart,
+	/// A loop end, `]`.
+	LoopEnd,
+	/// A run of other bytes on one line, not starting or ending with whitespace.
+	Comment,
+	/// A run of whitespace.
+	Whitespace,
+}
+
+/// A piece of source code.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Token<'s> {
+	/// What the token contains.
+	pub kind: TokenKind,
+	/// The source code of the token.
+	pub text: &'s [u8],
+	/// Where the token is.
+	pub span: Span,
+}
+
+/// Split `source` into tokens, keeping every byte.
+///
+/// Concatenating the text of the tokens gives back `source`.
+#[allow(clippy::missing_panics_doc)] // panic is exceptional
+pub fn lex(source: &[u8]) -> impl Iterator<Item = Token<'_>> {
+	let mut position = Position::START;
+	std::iter::from_fn(move || {
+		let rest = &source[position.offset..];
+		let &first = rest.first()?;
+
+		let (kind, len) = match first {
+			b'[' => (TokenKind::LoopStart, 1),
+			b']' => (TokenKind::LoopEnd, 1),
+			_ if is_instruction(first) => (
+				TokenKind::Instructions,
+				rest
+					.iter()
+					.take_while(|&&byte| is_instruction(byte))
+					.count(),
+			),
+			_ if first.is_ascii_whitespace() => (
+				TokenKind::Whitespace,
+				rest
+					.iter()
+					.take_while(|byte| byte.is_ascii_whitespace())
+					.count(),
+			),
+			_ => {
+				let line = rest
+					.iter()
+					.take_while(|&&byte| {
+						byte != b'\n' && !is_instruction(byte) && byte != b'[' && byte != b']'
+					})
+					.count();
+				// trailing whitespace belongs to the next token
+				let len = rest[..line]
+					.iter()
+					.rposition(|byte| !byte.is_ascii_whitespace())
+					.unwrap()
+					+ 1;
+				(TokenKind::Comment, len)
+			}
+		};
+
+		let text = &rest[..len];
+		let start = position;
+		position = text
+			.iter()
+			.fold(position, |position, &byte| position.advance(byte));
+		Some(Token {
+			kind,
+			text,
+			span: Span {
+				start,
+				end: position,
+			},
+		})
+	})
+}
+
+fn is_instruction(byte: u8) -> bool {
+	matches!(byte, b'+' | b'-' | b'<' | b'>' | b'.' | b',')
+}
+
+/// Options for [`format()`].
+///
+/// The default wraps lines at 80 columns and indents with two spaces.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FormatOptions {
+	line_width: usize,
+	indent: String,
+}
+
+impl Default for FormatOptions {
+	fn default() -> Self {
+		Self {
+			line_width: 80,
+			indent: "  ".to_owned(),
+		}
+	}
+}
+
+impl FormatOptions {
+	/// Loops with no more than this many instructions in them, none of them loops, and no comments are kept inline.
+	pub const INLINE_LOOP_MAX: usize = 12;
+
+	/// Wrap lines of code at `width` columns, counting indentation as its length in bytes.
+	///
+	/// Comments are never wrapped.
+	#[must_use]
+	pub fn line_width(self, width: usize) -> Self {
+		Self {
+			line_width: width,
+			..self
+		}
+	}
+
+	/// Indent each level of loop nesting with `indent`.
+	#[must_use]
+	pub fn indent(self, indent: impl Into<String>) -> Self {
+		Self {
+			indent: indent.into(),
+			..self
+		}
+	}
+}
+
+/// Format `source`.
+///
+/// # Errors
+///
+/// Returns `Err` iff there are unmatched loop starts or ends, since those can't be indented.
+pub fn format(source: &[u8], options: &FormatOptions) -> Result<Vec<u8>, Error> {
+	let tokens: Vec<_> = lex(source).collect();
+	let loop_ends = match_loops(&tokens)?;
+
+	let mut formatter = Formatter {
+		out: Vec::new(),
+		options,
+		depth: 0,
+		line: Vec::new(),
+		line_depth: 0,
+		line_kind: LineKind::Empty,
+		blank_pending: false,
+	};
+
+	let mut idx = 0;
+	while let Some(token) = tokens.get(idx) {
+		idx += 1;
+		match token.kind {
+			TokenKind::Whitespace => {
+				let newlines = token.text.split(|&byte| byte == b'\n').count() - 1;
+				if newlines >= 1 {
+					formatter.end_line();
+				}
+				if newlines >= 2 {
+					formatter.blank_pending = true;
+				}
+			}
+			TokenKind::Comment => {
+				if formatter.line_kind != LineKind::Empty {
+					formatter.line.push(b' ');
+				}
+				formatter.push(LineKind::Comment, token.text);
+				formatter.end_line();
+			}
+			TokenKind::Instructions => {
+				for &byte in token.text {
+					formatter.code(&[byte]);
+				}
+			}
+			TokenKind::LoopStart => {
+				let end = loop_ends[idx - 1];
+				let body = &tokens[idx..end];
+				let instructions = body
+					.iter()
+					.filter(|token| token.kind == TokenKind::Instructions)
+					.map(|token| token.text.len())
+					.sum::<usize>();
+				let inline = instructions <= FormatOptions::INLINE_LOOP_MAX
+					&& body
+						.iter()
+						.all(|token| matches!(token.kind, TokenKind::Instructions | TokenKind::Whitespace));
+
+				if inline {
+					let mut text = vec![b'['];
+					for token in body
+						.iter()
+						.filter(|token| token.kind == TokenKind::Instructions)
+					{
+						text.extend(token.text);
+					}
+					text.push(b']');
+					formatter.code(&text);
+					idx = end + 1;
+				} else {
+					formatter.end_line();
+					formatter.push(LineKind::Bracket, b"[");
+					formatter.depth += 1;
+				}
+			}
+			TokenKind::LoopEnd => {
+				formatter.end_line();
+				formatter.depth -= 1;
+				formatter.push(LineKind::Bracket, b"]");
+			}
+		}
+	}
+	formatter.end_line();
+
+	Ok(formatter.out)
+}
+
+/// Find the index of the matching loop end of each loop start.
+fn match_loops(tokens: &[Token<'_>]) -> Result<Vec<usize>, Error> {
+	let mut loop_ends = vec![0; tokens.len()];
+	let mut starts = Vec::new();
+	for (idx, token) in tokens.iter().enumerate() {
+		match token.kind {
+			TokenKind::LoopStart => starts.push(idx),
+			TokenKind::LoopEnd => {
+				let start = starts.pop().ok_or(Error::UnmatchedEnd(token.span.start))?;
+				loop_ends[start] = idx;
+			}
+			_ => {}
+		}
+	}
+
+	if starts.is_empty() {
+		Ok(loop_ends)
+	} else {
+		Err(Error::UnmatchedStart(
+			starts
+				.into_iter()
+				.map(|idx| tokens[idx].span.start)
+				.collect(),
+		))
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum LineKind {
+	Empty,
+	Code,
+	/// A line with a loop start or end that isn't inline, which can only be followed by a comment.
+	Bracket,
+	/// A line that ends with a comment, which can't be followed by anything.
+	Comment,
+}
+
+struct Formatter<'o> {
+	out: Vec<u8>,
+	options: &'o FormatOptions,
+	/// The loop nesting depth.
+	depth: usize,
+	/// The current line, without indentation.
+	line: Vec<u8>,
+	/// The nesting depth that the current line is indented by.
+	line_depth: usize,
+	line_kind: LineKind,
+	/// Whether to separate the next line with a blank line.
+	blank_pending: bool,
+}
+
+impl Formatter<'_> {
+	/// Add `text` to the current line, which becomes a line of `kind`.
+	fn push(&mut self, kind: LineKind, text: &[u8]) {
+		if self.line_kind == LineKind::Empty {
+			self.line_depth = self.depth;
+		}
+		self.line.extend(text);
+		self.line_kind = kind;
+	}
+
+	/// Add code that shouldn't be split across lines, starting a new line if it doesn't fit.
+	fn code(&mut self, text: &[u8]) {
+		match self.line_kind {
+			LineKind::Empty => {}
+			LineKind::Code => {
+				let indent = self.options.indent.len() * self.line_depth;
+				if indent + self.line.len() + text.len() > self.options.line_width {
+					self.end_line();
+				}
+			}
+			LineKind::Bracket | LineKind::Comment => self.end_line(),
+		}
+		self.push(LineKind::Code, text);
+	}
+
+	fn end_line(&mut self) {
+		if self.line_kind == LineKind::Empty {
+			return;
+		}
+		if self.blank_pending && !self.out.is_empty() {
+			self.out.push(b'\n');
+		}
+		self.blank_pending = false;
+
+		for _ in 0..self.line_depth {
+			self.out.extend(self.options.indent.as_bytes());
+		}
+		self.out.append(&mut self.line);
+		self.out.push(b'\n');
+		self.line_kind = LineKind::Empty;
+	}
+}
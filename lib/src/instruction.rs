@@ -52,8 +52,150 @@ pub enum Instruction<C: CellType> {
 	///
 	/// No direct equivalent. Emitted by the optimizer.
 	DecPtr(NonZeroU32),
+	/// Add the value of the current cell multiplied by the contained factor to the cell at the contained offset from the pointer.
+	///
+	/// Does nothing (not even checking the bounds of the target cell) if the current cell is zero.
+	///
+	/// No direct equivalent. Emitted by the optimizer for multiplication loops like `[->++<]`, followed by a `Set(0)`.
+	MulAdd(i32, C::NonZero),
+	/// Add the contained stride to the pointer until the current cell is zero.
+	///
+	/// With a stride of `1`, equivalent to `[>]`.
+	ScanRight(NonZeroU32),
+	/// Subtract the contained stride from the pointer until the current cell is zero.
+	///
+	/// With a stride of `1`, equivalent to `[<]`.
+	ScanLeft(NonZeroU32),
+	/// Set the value of the cell at the contained offset from the pointer.
+	///
+	/// No direct equivalent. Emitted by the optimizer.
+	SetAt(i32, C),
+	/// Add the contained value to the value in the cell at the contained offset from the pointer.
+	///
+	/// No direct equivalent. Emitted by the optimizer.
+	AddAt(i32, C::NonZero),
+	/// Read input into the cell at the contained offset from the pointer.
+	///
+	/// No direct equivalent. Emitted by the optimizer.
+	ReadAt(i32),
+	/// Output the value of the cell at the contained offset from the pointer.
+	///
+	/// No direct equivalent. Emitted by the optimizer.
+	WriteAt(i32),
+	/// Output the contained byte.
+	///
+	/// No direct equivalent. Emitted by the optimizer for output that is known at compile time.
+	WriteConst(u8),
+}
+
+/// Unwrap a nonzero field in a `const fn`.
+macro_rules! non_zero {
+	($ty:ident, $value:expr) => {
+		match std::num::$ty::new($value) {
+			Some(value) => value,
+			None => panic!("field must be nonzero"),
+		}
+	};
 }
 
+/// Constructors for the variants with nonzero fields that can be used in constants.
+///
+/// Loop instructions are constructed directly, and can be checked with [`Program`](crate::Program).
+impl<C: CellType> Instruction<C> {
+	/// Construct an [`IncPtr`](Self::IncPtr).
+	///
+	/// # Panics
+	///
+	/// Panics iff `amount` is zero.
+	#[must_use]
+	pub const fn inc_ptr(amount: u32) -> Self {
+		Self::IncPtr(non_zero!(NonZeroU32, amount))
+	}
+
+	/// Construct a [`DecPtr`](Self::DecPtr).
+	///
+	/// # Panics
+	///
+	/// Panics iff `amount` is zero.
+	#[must_use]
+	pub const fn dec_ptr(amount: u32) -> Self {
+		Self::DecPtr(non_zero!(NonZeroU32, amount))
+	}
+
+	/// Construct a [`ScanRight`](Self::ScanRight).
+	///
+	/// # Panics
+	///
+	/// Panics iff `stride` is zero.
+	#[must_use]
+	pub const fn scan_right(stride: u32) -> Self {
+		Self::ScanRight(non_zero!(NonZeroU32, stride))
+	}
+
+	/// Construct a [`ScanLeft`](Self::ScanLeft).
+	///
+	/// # Panics
+	///
+	/// Panics iff `stride` is zero.
+	#[must_use]
+	pub const fn scan_left(stride: u32) -> Self {
+		Self::ScanLeft(non_zero!(NonZeroU32, stride))
+	}
+}
+
+macro_rules! impl_const_constructors {
+	($ty:ty, $non_zero_ty:ident) => {
+		/// Constructors for the variants with nonzero cell fields that can be used in constants.
+		///
+		/// These exist for each cell type, so the cell type has to be named, like `Instruction::<u8>::inc(1)`.
+		impl Instruction<$ty> {
+			/// Construct an [`Inc`](Self::Inc).
+			///
+			/// # Panics
+			///
+			/// Panics iff `amount` is zero.
+			#[must_use]
+			pub const fn inc(amount: $ty) -> Self {
+				Self::Inc(non_zero!($non_zero_ty, amount))
+			}
+
+			/// Construct a [`Dec`](Self::Dec).
+			///
+			/// # Panics
+			///
+			/// Panics iff `amount` is zero.
+			#[must_use]
+			pub const fn dec(amount: $ty) -> Self {
+				Self::Dec(non_zero!($non_zero_ty, amount))
+			}
+
+			/// Construct a [`MulAdd`](Self::MulAdd).
+			///
+			/// # Panics
+			///
+			/// Panics iff `factor` is zero.
+			#[must_use]
+			pub const fn mul_add(offset: i32, factor: $ty) -> Self {
+				Self::MulAdd(offset, non_zero!($non_zero_ty, factor))
+			}
+
+			/// Construct an [`AddAt`](Self::AddAt).
+			///
+			/// # Panics
+			///
+			/// Panics iff `amount` is zero.
+			#[must_use]
+			pub const fn add_at(offset: i32, amount: $ty) -> Self {
+				Self::AddAt(offset, non_zero!($non_zero_ty, amount))
+			}
+		}
+	};
+}
+
+impl_const_constructors!(u8, NonZeroU8);
+impl_const_constructors!(u16, NonZeroU16);
+impl_const_constructors!(u32, NonZeroU32);
+
 /// The error that occurs when attempting to convert a non-instruction character to [`Instruction`].
 #[derive(Debug, thiserror::Error, Clone, Copy)]
 #[error("not an instruction")]
@@ -14,7 +14,7 @@ pub struct Builder<T, I, O> {
 	instruction_limit: Option<u64>,
 }
 
-impl<'i, 'o, T: Default> Builder<T, io::StdinLock<'i>, io::StdoutLock<'o>> {
+impl<T: Default> Builder<T, io::StdinLock<'_>, io::StdoutLock<'_>> {
 	/// Create a new builder, using stdin for the input and stdout for the output.
 	#[must_use]
 	pub fn stdio() -> Self {
@@ -50,6 +50,7 @@ impl<T, I, O> Builder<T, I, O> {
 			input: self.input,
 			output: self.output,
 			data_pointer: self.initial_data_pointer,
+			instruction_pointer: 0,
 			last_flush: std::time::Instant::now(),
 			#[cfg(feature = "limited")]
 			instructions_left: self.instruction_limit,
@@ -0,0 +1,121 @@
+//! Run instructions as machine code, handing over to the interpreter when the pointer leaves the data array.
+
+use std::any::Any;
+use std::ffi::c_void;
+use std::{io, mem, panic, ptr};
+
+use super::{Error, Interpreter};
+use crate::cell_type::CellType;
+use crate::compile::jit::{Compiled, STATUS_DONE, STATUS_IO_ERROR};
+use crate::instruction::Instruction;
+
+/// What the compiled code reads and writes with.
+struct Context<'i, T, I, O> {
+	interpreter: &'i mut Interpreter<T, I, O>,
+	/// The error from reading or writing that stopped the code.
+	error: Option<Error>,
+	/// The panic from reading or writing that stopped the code, to resume once it has returned.
+	panic: Option<Box<dyn Any + Send>>,
+}
+
+impl<T: CellType, I: io::Read, O: io::Write> Context<'_, T, I, O> {
+	/// Call `func` with the interpreter, keeping errors and panics since they can't go through the compiled code.
+	fn call<R>(
+		&mut self,
+		func: impl FnOnce(&mut Interpreter<T, I, O>) -> Result<R, Error>,
+	) -> Option<R> {
+		match panic::catch_unwind(panic::AssertUnwindSafe(|| func(self.interpreter))) {
+			Ok(Ok(value)) => Some(value),
+			Ok(Err(error)) => {
+				self.error = Some(error);
+				None
+			}
+			Err(payload) => {
+				self.panic = Some(payload);
+				None
+			}
+		}
+	}
+
+	/// Get the context that the compiled code passes back.
+	///
+	/// # Safety
+	///
+	/// `context` must point to a `Context` of this type that isn't otherwise in use.
+	unsafe fn from_raw<'c>(context: *mut c_void) -> &'c mut Self {
+		&mut *context.cast()
+	}
+}
+
+extern "sysv64" fn read<T: CellType, I: io::Read, O: io::Write>(context: *mut c_void) -> i32 {
+	// SAFETY: the compiled code passes the context given to `run`
+	let context = unsafe { Context::<T, I, O>::from_raw(context) };
+	context.call(Interpreter::read).map_or(-1, i32::from)
+}
+
+extern "sysv64" fn write<T: CellType, I: io::Read, O: io::Write>(
+	context: *mut c_void,
+	byte: u8,
+) -> i32 {
+	// SAFETY: the compiled code passes the context given to `run`
+	let context = unsafe { Context::<T, I, O>::from_raw(context) };
+	context
+		.call(|interpreter| interpreter.write(byte))
+		.map_or(-1, |()| 0)
+}
+
+/// Run `instructions` as machine code.
+///
+/// Returns `None` without running anything if they can't be compiled, or the pointer is already out of bounds.
+pub(super) fn run<T: CellType, I: io::Read, O: io::Write>(
+	interpreter: &mut Interpreter<T, I, O>,
+	instructions: &[Instruction<T>],
+) -> Option<Result<(), Error>> {
+	let cell_bytes = usize::from(T::BYTES);
+	let len = interpreter.data.len().checked_mul(cell_bytes)?;
+	if interpreter.data_pointer >= interpreter.data.len() {
+		return None;
+	}
+	let compiled = Compiled::new(instructions, read::<T, I, O>, write::<T, I, O>).ok()?;
+
+	// the compiled code has the only reference to the data while it runs
+	let mut data = mem::take(&mut interpreter.data);
+	let pointer = interpreter.data_pointer * cell_bytes;
+	let mut context = Context {
+		interpreter,
+		error: None,
+		panic: None,
+	};
+	// SAFETY: `read` and `write` take this context, and the pointer is in the data array of `len` bytes
+	let stop = unsafe {
+		compiled.run(
+			ptr::addr_of_mut!(context).cast(),
+			data.as_mut_ptr().cast(),
+			pointer,
+			len,
+		)
+	};
+	let Context {
+		interpreter,
+		error,
+		panic,
+	} = context;
+	interpreter.data = data;
+	if let Some(payload) = panic {
+		panic::resume_unwind(payload);
+	}
+
+	interpreter.data_pointer = stop.pointer / cell_bytes;
+	let mut index = stop.index;
+	let result = match stop.status {
+		STATUS_DONE => {
+			index = instructions.len();
+			Ok(())
+		}
+		STATUS_IO_ERROR => Err(error.expect("reading or writing failed")),
+		// the pointer is back where it was before the instruction, so interpreting it fails the same way
+		_ => interpreter.run_from(instructions, &mut index),
+	};
+	interpreter.instruction_pointer = index;
+	Some(result)
+}
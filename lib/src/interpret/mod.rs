@@ -7,8 +7,11 @@ use thiserror::Error;
 
 use crate::cell_type::CellType;
 use crate::instruction::Instruction;
+use crate::program::Program;
 
 mod builder;
+#[cfg(all(feature = "jit", target_os = "linux", target_arch = "x86_64"))]
+mod jit;
 pub use builder::Builder;
 
 fn compare_error(a: &io::Error, b: &io::Error) -> bool {
@@ -55,6 +58,7 @@ pub struct Interpreter<T, I, O> {
 	input: I,
 	data: Box<[T]>,
 	data_pointer: usize,
+	instruction_pointer: usize,
 	last_flush: Instant,
 	#[cfg(feature = "limited")]
 	instructions_left: Option<u64>,
@@ -113,6 +117,49 @@ impl<T: CellType, I: io::Read, O: io::Write> Interpreter<T, I, O> {
 		Ok(())
 	}
 
+	/// Move the pointer right by `stride` until it reaches a zero cell.
+	#[inline]
+	fn scan_right(&mut self, stride: usize) -> Result<(), Error> {
+		let steps = self.data[self.data_pointer..]
+			.iter()
+			.step_by(stride)
+			.position(|&cell| cell == T::ZERO)
+			.ok_or(Error::Overflow)?;
+		self.data_pointer += steps * stride;
+		Ok(())
+	}
+
+	/// Move the pointer left by `stride` until it reaches a zero cell.
+	#[inline]
+	fn scan_left(&mut self, stride: usize) -> Result<(), Error> {
+		let steps = self.data[..=self.data_pointer]
+			.iter()
+			.rev()
+			.step_by(stride)
+			.position(|&cell| cell == T::ZERO)
+			.ok_or(Error::Underflow)?;
+		self.data_pointer -= steps * stride;
+		Ok(())
+	}
+
+	/// Get the index of the cell at `offset` from the pointer, checking that it is in bounds.
+	#[inline]
+	fn offset_index(&self, offset: i32) -> Result<usize, Error> {
+		let distance = usize::try_from(offset.unsigned_abs()).unwrap();
+		if offset < 0 {
+			self
+				.data_pointer
+				.checked_sub(distance)
+				.ok_or(Error::Underflow)
+		} else {
+			self
+				.data_pointer
+				.checked_add(distance)
+				.filter(|&idx| idx < self.data.len())
+				.ok_or(Error::Overflow)
+		}
+	}
+
 	#[inline]
 	fn write(&mut self, v: u8) -> Result<(), Error> {
 		self.output.write_all(&[v]).map_err(Error::OutputIo)?;
@@ -134,25 +181,89 @@ impl<T: CellType, I: io::Read, O: io::Write> Interpreter<T, I, O> {
 			.map_err(Error::InputIo)
 	}
 
-	/// Run the interpreter on the given instruction stream.
+	/// Run the interpreter on the given program.
 	///
-	/// If you want to run an interpreter based on the output of the compiler, use the `instructions` method on the compiler to get the instructions.
+	/// An [`InstructionStream`](crate::InstructionStream) from the compiler can be passed by reference, and hand-built instructions can be checked with [`Program::new`].
+	/// This used to take a slice of instructions, which ran even if its jump targets were wrong.
 	///
 	/// # Errors
 	///
 	/// See the variants of [Error].
+	/// Use [`instruction_pointer`](Self::instruction_pointer) to find which instruction caused the error.
+	pub fn run<'p>(&mut self, program: impl Into<Program<'p, T>>) -> Result<(), Error>
+	where
+		T: 'p,
+	{
+		let mut instruction_pointer = 0;
+		let result = self.run_from(program.into().instructions(), &mut instruction_pointer);
+		self.instruction_pointer = instruction_pointer;
+		result
+	}
+
+	/// Run the instructions of `program` in `range`, keeping the data and instruction limit from earlier runs.
+	///
+	/// `range` should start and end between top-level instructions, so no loop jumps out of it.
+	#[cfg(feature = "limited")]
+	pub(crate) fn run_range(
+		&mut self,
+		program: Program<'_, T>,
+		range: std::ops::Range<usize>,
+	) -> Result<(), Error> {
+		let mut instruction_pointer = range.start;
+		let result = self.run_from(
+			&program.instructions()[..range.end],
+			&mut instruction_pointer,
+		);
+		self.instruction_pointer = instruction_pointer;
+		result
+	}
+
+	/// Run the interpreter on the given program by compiling it to machine code first, which is faster for long-running programs.
+	///
+	/// The result is the same as [`run`](Self::run), including the errors and where they occurred.
+	/// Code is only compiled on x86-64 Linux, so the program is interpreted on other platforms, and when there is an instruction limit.
+	///
+	/// # Errors
+	///
+	/// See the variants of [Error].
+	#[cfg(feature = "jit")]
+	pub fn run_jit<'p>(&mut self, program: impl Into<Program<'p, T>>) -> Result<(), Error>
+	where
+		T: 'p,
+	{
+		let program = program.into();
+		#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+		{
+			// compiled code doesn't count instructions
+			#[cfg(feature = "limited")]
+			let limited = self.instructions_left.is_some();
+			#[cfg(not(feature = "limited"))]
+			let limited = false;
+
+			if !limited {
+				if let Some(result) = jit::run(self, program.instructions()) {
+					return result;
+				}
+			}
+		}
+		self.run(program)
+	}
+
+	#[inline]
 	#[allow(clippy::missing_panics_doc)] // panics are exceptional
-	pub fn run(&mut self, stream: &[Instruction<T>]) -> Result<(), Error> {
-		let mut instruction_pointer = 0usize;
+	fn run_from(
+		&mut self,
+		stream: &[Instruction<T>],
+		instruction_pointer: &mut usize,
+	) -> Result<(), Error> {
 		let len = stream.len();
-
 		// SAFETY: check the pointer now to ensure it's in bounds before any `_unchecked` ops assume so.
 		if self.data_pointer >= self.data.len() {
 			return Err(Error::InitOverflow);
 		}
 
 		// SAFETY: `ptr` bounds are checked by `ptr` mutating operations, so it will remain valid within this block.
-		while instruction_pointer < len {
+		while *instruction_pointer < len {
 			#[cfg(feature = "limited")]
 			if let Some(0) = self.instructions_left {
 				return Err(Error::NotEnoughInstructions);
@@ -160,7 +271,7 @@ impl<T: CellType, I: io::Read, O: io::Write> Interpreter<T, I, O> {
 
 			unsafe {
 				use Instruction as I;
-				match *stream.get_unchecked(instruction_pointer) {
+				match *stream.get_unchecked(*instruction_pointer) {
 					I::Set(value) => self.map_current(|_| value),
 					I::Inc(amount) => self.map_current(|c| c.wrapping_add(amount.into())),
 					I::Dec(amount) => self.map_current(|c| c.wrapping_sub(amount.into())),
@@ -171,20 +282,47 @@ impl<T: CellType, I: io::Read, O: io::Write> Interpreter<T, I, O> {
 						let new = self.read()?.into();
 						self.map_current(|_| new);
 					}
+					I::MulAdd(offset, factor) => {
+						let value = self.cur_unchecked();
+						if value != T::ZERO {
+							let idx = self.offset_index(offset)?;
+							let target = &mut self.data[idx];
+							*target = target.wrapping_add(value.wrapping_mul(factor.into()));
+						}
+					}
+					I::ScanRight(stride) => self.scan_right(usize::try_from(stride.get()).unwrap())?,
+					I::ScanLeft(stride) => self.scan_left(usize::try_from(stride.get()).unwrap())?,
+					I::SetAt(offset, value) => {
+						let idx = self.offset_index(offset)?;
+						self.data[idx] = value;
+					}
+					I::AddAt(offset, amount) => {
+						let idx = self.offset_index(offset)?;
+						self.data[idx] = self.data[idx].wrapping_add(amount.into());
+					}
+					I::ReadAt(offset) => {
+						let idx = self.offset_index(offset)?;
+						self.data[idx] = self.read()?.into();
+					}
+					I::WriteAt(offset) => {
+						let idx = self.offset_index(offset)?;
+						self.write(self.data[idx].truncate_to_byte())?;
+					}
+					I::WriteConst(byte) => self.write(byte)?,
 					I::LoopStart(end) => {
 						if self.cur_unchecked() == T::ZERO {
-							instruction_pointer = end as usize;
+							*instruction_pointer = end as usize;
 						}
 					}
 					I::LoopEnd(start) => {
 						if self.cur_unchecked() != T::ZERO {
-							instruction_pointer = start as usize;
+							*instruction_pointer = start as usize;
 						}
 					}
 				}
 			}
 
-			instruction_pointer += 1;
+			*instruction_pointer += 1;
 
 			#[cfg(feature = "limited")]
 			if let Some(left) = &mut self.instructions_left {
@@ -207,6 +345,15 @@ impl<T: CellType, I: io::Read, O: io::Write> Interpreter<T, I, O> {
 		self.data
 	}
 
+	/// Get the index of the instruction that the last call to [`run`](Self::run) stopped at.
+	///
+	/// If it returned `Err`, this is the instruction that caused the error, unless the error occurred before any instructions were run.
+	/// If it returned `Ok`, this is the length of the stream.
+	#[must_use]
+	pub fn instruction_pointer(&self) -> usize {
+		self.instruction_pointer
+	}
+
 	/// Get the data pointer.
 	#[must_use]
 	pub fn data_pointer(&self) -> usize {
@@ -10,8 +10,6 @@
 	non_ascii_idents,
 	nonstandard_style,
 	noop_method_call,
-	pointer_structural_match,
-	private_in_public,
 	rust_2018_idioms,
 	unused_qualifications
 )]
@@ -24,8 +22,12 @@
 
 pub mod cell_type;
 pub mod compile;
+pub mod format;
 pub mod instruction;
 pub mod interpret;
+pub mod lint;
+pub mod program;
+pub mod span;
 #[cfg(test)]
 mod test;
 
@@ -33,6 +35,7 @@ pub use cell_type::CellType;
 pub use compile::InstructionStream;
 pub use instruction::Instruction;
 pub use interpret::Interpreter;
+pub use program::Program;
 
 /// Convenience method to compile and optimize the code in `input`.
 ///
@@ -52,7 +55,7 @@ pub fn interpret<T: CellType>(stream: &InstructionStream<T>) -> Result<(), inter
 	Interpreter::build_stdio()
 		.configure_for(stream)
 		.build()
-		.run(stream.instructions())
+		.run(stream)
 }
 
 /// Convenience method to compile, optimize, and interpret the code in `input`.
@@ -0,0 +1,215 @@
+//! Finds likely mistakes in Brainfuck code.
+
+use std::fmt;
+use std::ops::Range;
+
+use crate::cell_type::CellType;
+use crate::compile::{self, InstructionStream, KnownCells};
+use crate::instruction::Instruction;
+use crate::span::Span;
+
+/// A kind of likely mistake.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[non_exhaustive]
+pub enum Kind {
+	/// Two adjacent instructions that undo each other, like `+-` or `<>`.
+	CancellingPair,
+	/// A loop that can never be entered, because the current cell is always zero when it's reached.
+	UnreachableLoop,
+	/// A loop that never ends once it's reached, like `+[]`.
+	InfiniteLoop,
+	/// A comment loop at the start of the program that contains instruction characters.
+	///
+	/// The loop is never entered, but the characters were probably meant as part of the comment.
+	CommentLoopInstructions,
+	/// Code after an infinite loop.
+	UnreachableCode,
+	/// A pointer move that always goes below the start of the data array.
+	PointerUnderflow,
+}
+
+impl Kind {
+	/// A stable, machine-readable name for the kind, like `cancelling-pair`.
+	#[must_use]
+	pub fn name(self) -> &'static str {
+		match self {
+			Self::CancellingPair => "cancelling-pair",
+			Self::UnreachableLoop => "unreachable-loop",
+			Self::InfiniteLoop => "infinite-loop",
+			Self::CommentLoopInstructions => "comment-loop-instructions",
+			Self::UnreachableCode => "unreachable-code",
+			Self::PointerUnderflow => "pointer-underflow",
+		}
+	}
+}
+
+impl fmt::Display for Kind {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			Self::CancellingPair => "these instructions cancel each other out",
+			Self::UnreachableLoop => "this loop is never entered because the current cell is always zero",
+			Self::InfiniteLoop => "this loop never ends",
+			Self::CommentLoopInstructions => "this comment loop contains instruction characters",
+			Self::UnreachableCode => "this code never runs because of the infinite loop before it",
+			Self::PointerUnderflow => "this moves the pointer below the start of the data array",
+		})
+	}
+}
+
+/// A likely mistake in the source code.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Warning {
+	/// What the mistake is.
+	pub kind: Kind,
+	/// Where the mistake is.
+	pub span: Span,
+}
+
+/// Find likely mistakes in `source`, in order of where they start.
+///
+/// Loops are assumed to start with a zeroed data array and the pointer at its start.
+///
+/// # Errors
+///
+/// Returns `Err` iff there are unmatched loop starts or ends.
+#[allow(clippy::missing_panics_doc)] // panic is exceptional
+pub fn lint<T: CellType>(source: &[u8]) -> Result<Vec<Warning>, compile::Error> {
+	let stream = InstructionStream::<T>::from_code_with_spans(source.iter().copied())?;
+	let instructions = stream.instructions();
+	let spans = stream.spans().unwrap();
+	let span_of = |range: Range<usize>| spans[range].iter().copied().reduce(Span::merge).unwrap();
+
+	let mut warnings = Vec::new();
+	let dead = lint_flow(instructions, &mut |kind, range| {
+		warnings.push(Warning {
+			kind,
+			span: span_of(range),
+		});
+	});
+
+	// cancelling pairs in code that never runs would just be noise
+	let mut idx = 0;
+	while idx + 1 < instructions.len() {
+		if dead.iter().any(|range| range.contains(&idx)) {
+			idx += 1;
+			continue;
+		}
+		if matches!(
+			(instructions[idx], instructions[idx + 1]),
+			(Instruction::Inc(..), Instruction::Dec(..))
+				| (Instruction::Dec(..), Instruction::Inc(..))
+				| (Instruction::IncPtr(..), Instruction::DecPtr(..))
+				| (Instruction::DecPtr(..), Instruction::IncPtr(..))
+		) {
+			warnings.push(Warning {
+				kind: Kind::CancellingPair,
+				span: span_of(idx..idx + 2),
+			});
+			idx += 2;
+		} else {
+			idx += 1;
+		}
+	}
+
+	warnings.sort_by_key(|warning| warning.span.start);
+	Ok(warnings)
+}
+
+/// Follow the control flow of an unoptimized stream, reporting each warning with the range of instructions it covers.
+///
+/// Returns the ranges of instructions that never run.
+fn lint_flow<T: CellType>(
+	instructions: &[Instruction<T>],
+	warn: &mut impl FnMut(Kind, Range<usize>),
+) -> Vec<Range<usize>> {
+	let mut dead = Vec::new();
+	let mut known = KnownCells::<T>::all(Some(T::ZERO));
+	// how far the pointer is from the start of the data array, until it's moved by a loop
+	let mut position = Some(0i64);
+	// for each loop that is currently open, its end and what is known if it is skipped, or `None` if it is always entered
+	let mut open = Vec::<(usize, Option<KnownCells<T>>)>::new();
+
+	let mut idx = 0;
+	while idx < instructions.len() {
+		match instructions[idx] {
+			Instruction::LoopStart(end) => {
+				let end = end as usize;
+				let body = &instructions[idx + 1..end];
+				match known.get(0) {
+					Some(value) if value == T::ZERO => {
+						if idx != 0 {
+							warn(Kind::UnreachableLoop, idx..end + 1);
+						} else if !body.is_empty() {
+							warn(Kind::CommentLoopInstructions, idx..end + 1);
+						}
+						dead.push(idx..end + 1);
+						idx = end + 1;
+						continue;
+					}
+					Some(_)
+						if body
+							.iter()
+							.all(|instruction| matches!(instruction, Instruction::Write)) =>
+					{
+						warn(Kind::InfiniteLoop, idx..end + 1);
+						// the rest of the program only never runs if every loop around this one is always entered.
+						// otherwise, the rest of the innermost loop that can be skipped never runs, and the code after it runs when it's skipped.
+						while let Some((_, None)) = open.last() {
+							open.pop();
+						}
+						let resume = open.last().map_or(instructions.len(), |&(end, _)| end);
+						if end + 1 < resume {
+							warn(Kind::UnreachableCode, end + 1..resume);
+							dead.push(end + 1..resume);
+						}
+						match open.pop() {
+							Some((end, skipped)) => {
+								known = skipped.unwrap();
+								idx = end + 1;
+								continue;
+							}
+							None => break,
+						}
+					}
+					value => {
+						open.push((
+							end,
+							value.is_none().then(|| {
+								let mut skipped = known.clone();
+								skipped.set(0, Some(T::ZERO));
+								skipped
+							}),
+						));
+						// the body may run any number of times, so nothing is known at its start
+						known = KnownCells::all(None);
+						position = None;
+					}
+				}
+			}
+			Instruction::LoopEnd(_) => {
+				known.set(0, Some(T::ZERO));
+				if let Some((_, Some(skipped))) = open.pop() {
+					known = known.join(&skipped);
+				}
+			}
+			instruction => {
+				if let Some(pointer) = &mut position {
+					match instruction {
+						Instruction::IncPtr(amount) => *pointer += i64::from(amount.get()),
+						Instruction::DecPtr(amount) => *pointer -= i64::from(amount.get()),
+						_ => {}
+					}
+					if *pointer < 0 {
+						warn(Kind::PointerUnderflow, idx..idx + 1);
+						// the program has already failed, so later moves would only repeat this warning
+						position = None;
+					}
+				}
+				known.transfer(instruction);
+			}
+		}
+		idx += 1;
+	}
+
+	dead
+}
@@ -0,0 +1,146 @@
+//! Provides [`Program`], a slice of instructions with checked jump targets.
+
+use crate::cell_type::CellType;
+use crate::compile::InstructionStream;
+use crate::instruction::Instruction;
+
+/// Errors that can occur while validating a [`Program`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug, thiserror::Error)]
+pub enum Error {
+	/// A loop instruction jumps past the end of the instructions.
+	#[error("jump target of instruction {0} is out of range")]
+	OutOfRange(usize),
+	/// A loop instruction jumps somewhere other than its matching loop instruction.
+	#[error("instruction {0} does not jump to its matching loop instruction")]
+	Mismatched(usize),
+}
+
+impl Error {
+	/// The index of the instruction with the invalid jump target.
+	#[must_use]
+	pub const fn index(self) -> usize {
+		match self {
+			Self::OutOfRange(index) | Self::Mismatched(index) => index,
+		}
+	}
+}
+
+/// Instructions whose loops are balanced and whose jump targets point at their matching loop instruction.
+///
+/// This is what [`Interpreter::run`](crate::Interpreter::run) takes.
+/// Instruction streams are always valid, so they convert into programs directly, while hand-built instructions are checked with [`new`](Self::new):
+///
+/// ```
+/// use bfirs::{Instruction, Program};
+///
+/// type I = Instruction<u8>;
+/// const CAT: &[I] = &[I::Read, I::LoopStart(4), I::Write, I::Read, I::LoopEnd(1)];
+/// const PROGRAM: Program<'static, u8> = Program::expect_valid(CAT);
+///
+/// let mut output = Vec::new();
+/// let mut interpreter = bfirs::Interpreter::build(&b"cat"[..], &mut output).build();
+/// interpreter.run(PROGRAM).unwrap();
+/// assert_eq!(output, b"cat");
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Program<'i, T: CellType> {
+	instructions: &'i [Instruction<T>],
+}
+
+impl<'i, T: CellType> Program<'i, T> {
+	/// Check that the loops in `instructions` are balanced and that every loop instruction jumps to its matching one.
+	///
+	/// # Errors
+	///
+	/// Returns `Err` with the first instruction that has an invalid jump target.
+	pub const fn new(instructions: &'i [Instruction<T>]) -> Result<Self, Error> {
+		let len = instructions.len();
+		let mut idx = 0;
+		while idx < len {
+			match instructions[idx] {
+				Instruction::LoopStart(end) => {
+					let end = end as usize;
+					if end >= len {
+						return Err(Error::OutOfRange(idx));
+					}
+					if end <= idx
+						|| !matches!(instructions[end], Instruction::LoopEnd(start) if start as usize == idx)
+					{
+						return Err(Error::Mismatched(idx));
+					}
+					// walk the body, skipping over inner loops, which are checked when they are reached
+					let mut inner = idx + 1;
+					while inner < end {
+						match instructions[inner] {
+							Instruction::LoopStart(inner_end) => {
+								let inner_end = inner_end as usize;
+								if inner_end <= inner || inner_end >= end {
+									return Err(Error::Mismatched(inner));
+								}
+								inner = inner_end + 1;
+							}
+							Instruction::LoopEnd(..) => return Err(Error::Mismatched(inner)),
+							_ => inner += 1,
+						}
+					}
+					if inner != end {
+						return Err(Error::Mismatched(idx));
+					}
+				}
+				Instruction::LoopEnd(start) => {
+					let start = start as usize;
+					if start >= len {
+						return Err(Error::OutOfRange(idx));
+					}
+					if start >= idx
+						|| !matches!(instructions[start], Instruction::LoopStart(end) if end as usize == idx)
+					{
+						return Err(Error::Mismatched(idx));
+					}
+				}
+				_ => {}
+			}
+			idx += 1;
+		}
+
+		Ok(Self { instructions })
+	}
+
+	/// Like [`new`](Self::new), but panics if the instructions are invalid.
+	///
+	/// When used to initialize a constant, invalid instructions fail to compile.
+	///
+	/// # Panics
+	///
+	/// Panics iff [`new`](Self::new) returns `Err`.
+	#[must_use]
+	pub const fn expect_valid(instructions: &'i [Instruction<T>]) -> Self {
+		match Self::new(instructions) {
+			Ok(program) => program,
+			Err(Error::OutOfRange(..)) => panic!("jump target out of range"),
+			Err(Error::Mismatched(..)) => panic!("jump target does not match"),
+		}
+	}
+
+	/// Get the instructions in the program.
+	#[must_use]
+	pub const fn instructions(self) -> &'i [Instruction<T>] {
+		self.instructions
+	}
+}
+
+impl<'i, T: CellType> From<&'i InstructionStream<T>> for Program<'i, T> {
+	fn from(stream: &'i InstructionStream<T>) -> Self {
+		Self {
+			instructions: stream.instructions(),
+		}
+	}
+}
+
+impl<'i, T: CellType> TryFrom<&'i [Instruction<T>]> for Program<'i, T> {
+	type Error = Error;
+
+	fn try_from(instructions: &'i [Instruction<T>]) -> Result<Self, Error> {
+		Self::new(instructions)
+	}
+}
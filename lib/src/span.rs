@@ -0,0 +1,89 @@
+//! Provides [`Position`] and [`Span`] for mapping instructions back to source code.
+
+use std::fmt;
+use std::ops::Range;
+
+/// A position in source code.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Position {
+	/// The byte offset from the start of the source.
+	pub offset: usize,
+	/// The line, starting at 1.
+	pub line: usize,
+	/// The column in bytes, starting at 1.
+	pub column: usize,
+}
+
+impl Position {
+	/// The position of the start of the source.
+	pub const START: Self = Self {
+		offset: 0,
+		line: 1,
+		column: 1,
+	};
+
+	/// Get the position after `byte`, given that `byte` is at this position.
+	#[must_use]
+	pub fn advance(self, byte: u8) -> Self {
+		if byte == b'\n' {
+			Self {
+				offset: self.offset + 1,
+				line: self.line + 1,
+				column: 1,
+			}
+		} else {
+			Self {
+				offset: self.offset + 1,
+				column: self.column + 1,
+				..self
+			}
+		}
+	}
+}
+
+impl fmt::Display for Position {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}:{}", self.line, self.column)
+	}
+}
+
+/// A range of source code.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Span {
+	/// The position of the first byte in the range.
+	pub start: Position,
+	/// The position after the last byte in the range.
+	pub end: Position,
+}
+
+impl Span {
+	/// Get the span of the single byte `byte` at `start`.
+	#[must_use]
+	pub fn of_byte(start: Position, byte: u8) -> Self {
+		Self {
+			start,
+			end: start.advance(byte),
+		}
+	}
+
+	/// Get the smallest span that contains both spans.
+	#[must_use]
+	pub fn merge(self, other: Self) -> Self {
+		Self {
+			start: self.start.min(other.start),
+			end: self.end.max(other.end),
+		}
+	}
+
+	/// Get the range of byte offsets covered by this span.
+	#[must_use]
+	pub fn range(&self) -> Range<usize> {
+		self.start.offset..self.end.offset
+	}
+}
+
+impl fmt::Display for Span {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.start)
+	}
+}
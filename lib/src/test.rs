@@ -35,7 +35,7 @@ fn run_output(input: &str, optimize: bool) -> Result<Vec<u8>, Error> {
 	let mut interpreter = crate::Interpreter::build(std::io::empty(), &mut out)
 		.instruction_limit(1_000_000)
 		.build();
-	let result = interpreter.run(stream.instructions());
+	let result = interpreter.run(&stream);
 	result.map(|()| out)
 }
 
@@ -44,8 +44,414 @@ pipeline_tests![
 	"<" => Err(Error::Underflow),
 	"+[>+]" => Err(Error::Overflow),
 	"+[]" => Err(Error::NotEnoughInstructions),
+	"++++++++[->++++++++<]>+." => Ok(&b"A"),
+	"++++++++[>++++>++++++++<<-]>>+.<+." => Ok(&b"A!"),
+	"++++[+>+<]>." => Ok(&[252]),
+	"+[<+>-]" => Err(Error::Underflow),
+	"[<+>-]" => Ok(&b""),
+	"+>+>+>>+<<<<[>]+++++++++[-<++++++++>]<." => Ok(&b"I"),
+	">>+>>+>>>+<<<[>>]+[<<]>>>>>>." => Ok(&[1]),
+	"+[<<]" => Err(Error::Underflow),
+	">+>++<<->>>+++++++[<<<+++++++++>>>-]<<<++.>." => Ok(&[64, 1]),
+	">>++<.>[<+>-]+<<+>[>.<-]" => Ok(&[0, 1, 1]),
+	"+>-<[>>+<.>-<<->]" => Ok(&[255, 0]),
 ];
 
+#[test]
+fn offset_instructions() {
+	use std::num::{NonZeroU32, NonZeroU8};
+
+	use crate::Instruction;
+
+	let stream = crate::compile::<u8>(",[>+>++<<->.<<+>->-]").unwrap();
+	let non_zero = |value| NonZeroU8::new(value).unwrap();
+	assert_eq!(
+		stream.instructions(),
+		&[
+			Instruction::Read,
+			Instruction::LoopStart(9),
+			Instruction::AddAt(1, non_zero(1)),
+			Instruction::AddAt(2, non_zero(2)),
+			Instruction::Dec(non_zero(2)),
+			Instruction::WriteAt(1),
+			Instruction::AddAt(-1, non_zero(1)),
+			Instruction::AddAt(1, non_zero(255)),
+			Instruction::IncPtr(NonZeroU32::new(1).unwrap()),
+			Instruction::LoopEnd(1),
+		]
+	);
+}
+
+#[test]
+fn scan_loops() {
+	use crate::{Instruction, Program};
+
+	let stream = crate::compile::<u8>(",[>],[<<<]").unwrap();
+	assert!(matches!(
+		stream.instructions(),
+		&[Instruction::Read, Instruction::ScanRight(one), Instruction::Read, Instruction::ScanLeft(three)] if one.get() == 1 && three.get() == 3
+	));
+
+	let mut interpreter = crate::Interpreter::build(std::io::empty(), std::io::sink())
+		.data_array_size(10)
+		.fill(1u8)
+		.build();
+	assert_eq!(
+		interpreter.run(Program::new(&[Instruction::scan_right(3)]).unwrap()),
+		Err(Error::Overflow)
+	);
+}
+
+#[test]
+fn constant_propagation() {
+	use crate::compile::OptimizeOptions;
+	use crate::Instruction;
+
+	let code = "[comment, loop.]+++>[-]++[->+<]<.";
+	let mut stream = crate::InstructionStream::<u8>::from_code(code.bytes()).unwrap();
+	stream
+		.optimize_with(&mut OptimizeOptions::default().constants(true))
+		.unwrap();
+	assert_eq!(
+		stream.instructions(),
+		&[
+			Instruction::Set(3),
+			Instruction::SetAt(2, 2),
+			Instruction::Write,
+		]
+	);
+
+	// the default options don't assume that the data array is zeroed
+	for code in [code, ".", "[-]+.", "+[-[>+<-]]>.", "+>[.-]<."] {
+		let run = |optimize: bool| {
+			let mut stream = crate::InstructionStream::<u8>::from_code(code.bytes()).unwrap();
+			if optimize {
+				stream.optimize().unwrap();
+			}
+			let mut out = Vec::new();
+			crate::Interpreter::build(std::io::empty(), &mut out)
+				.fill(7)
+				.build()
+				.run(&stream)
+				.unwrap();
+			out
+		};
+		assert_eq!(run(false), run(true), "{code:?}");
+	}
+}
+
+#[test]
+fn prefix_evaluation() {
+	use crate::compile::OptimizeOptions;
+	use crate::Instruction;
+
+	let mut stream = crate::InstructionStream::<u8>::from_code(HELLO.bytes()).unwrap();
+	stream
+		.optimize_with(&mut OptimizeOptions::default().prefix_evaluation(Some(1_000_000)))
+		.unwrap();
+	let output: Vec<u8> = stream
+		.instructions()
+		.iter()
+		.filter_map(|instruction| match instruction {
+			Instruction::WriteConst(byte) => Some(*byte),
+			_ => None,
+		})
+		.collect();
+	assert_eq!(output, b"Hello World!\n");
+
+	// the loop needs more than the budget, so only the part before it is evaluated
+	let mut stream =
+		crate::InstructionStream::<u8>::from_code("+.>++[>+++[-]<-].,.".bytes()).unwrap();
+	stream
+		.optimize_with(&mut OptimizeOptions::default().prefix_evaluation(Some(5)))
+		.unwrap();
+	assert!(matches!(
+		stream.instructions(),
+		&[
+			Instruction::WriteConst(1),
+			Instruction::Set(1),
+			Instruction::SetAt(1, 2),
+			Instruction::IncPtr(..),
+			Instruction::LoopStart(..),
+			..
+		]
+	));
+
+	// the budget runs out after the loop has already written, which must not be kept
+	let mut stream = crate::InstructionStream::<u8>::from_code("+++[.-]>,.".bytes()).unwrap();
+	stream
+		.optimize_with(&mut OptimizeOptions::default().prefix_evaluation(Some(4)))
+		.unwrap();
+	assert!(matches!(
+		stream.instructions(),
+		&[Instruction::Set(3), Instruction::LoopStart(..), ..]
+	));
+
+	let mut stream =
+		crate::InstructionStream::<u8>::from_code("+.>++[>+++[-]<-].,.".bytes()).unwrap();
+	stream
+		.optimize_with(&mut OptimizeOptions::default().prefix_evaluation(Some(1_000)))
+		.unwrap();
+	assert_eq!(
+		stream.instructions(),
+		&[
+			Instruction::WriteConst(1),
+			Instruction::WriteConst(0),
+			Instruction::Set(1),
+			Instruction::ReadAt(1),
+			Instruction::WriteAt(1),
+			Instruction::IncPtr(std::num::NonZeroU32::new(1).unwrap()),
+		]
+	);
+}
+
+#[test]
+fn optimize_options() {
+	use crate::compile::{OptimizeOptions, Pass};
+	use crate::span::Span;
+	use crate::Instruction;
+
+	struct RemoveWrites;
+
+	impl Pass<u8> for RemoveWrites {
+		fn name(&self) -> &'static str {
+			"remove writes"
+		}
+
+		fn run(&mut self, instructions: &mut Vec<Instruction<u8>>, _spans: Option<&mut Vec<Span>>) {
+			instructions.retain(|instruction| !matches!(instruction, Instruction::Write));
+		}
+	}
+
+	let code = ",[->+<].";
+
+	let mut stream = crate::InstructionStream::<u8>::from_code(code.bytes()).unwrap();
+	let stats = stream
+		.optimize_with(&mut OptimizeOptions::level(0))
+		.unwrap();
+	assert!(stats.passes.is_empty());
+	assert_eq!(stream.instructions().len(), 8);
+
+	let mut stream = crate::InstructionStream::<u8>::from_code(code.bytes()).unwrap();
+	let stats = stream
+		.optimize_with(&mut OptimizeOptions::default().scans(false).pass(RemoveWrites))
+		.unwrap();
+	assert!(stats.passes.iter().all(|pass| pass.name != "scans"));
+	assert_eq!(stats.instructions_removed_by("multiplications"), 4);
+	assert_eq!(stats.instructions_removed_by("remove writes"), 1);
+	assert!(!stream.instructions().contains(&Instruction::Write));
+}
+
+#[test]
+fn multiplication_loops() {
+	use crate::Instruction;
+
+	let stream = crate::compile::<u8>(",[->+>---<<]").unwrap();
+	assert!(matches!(
+		stream.instructions(),
+		&[
+			Instruction::Read,
+			Instruction::MulAdd(1, one),
+			Instruction::MulAdd(2, minus_three),
+			Instruction::Set(0),
+		] if one.get() == 1 && minus_three.get() == 253
+	));
+
+	// not balanced, so not a multiplication loop
+	let stream = crate::compile::<u8>(",[->+]").unwrap();
+	assert!(stream
+		.instructions()
+		.iter()
+		.all(|instruction| !matches!(instruction, Instruction::MulAdd(..))));
+}
+
+#[test]
+fn source_spans() {
+	let mut stream =
+		crate::InstructionStream::<u8>::from_code_with_spans(",+++\n  <".bytes()).unwrap();
+	stream.optimize().unwrap();
+	let spans = stream.spans().unwrap();
+	assert_eq!(spans.len(), stream.instructions().len());
+	// the folded `+++`
+	assert_eq!(spans[1].range(), 1..4);
+
+	let mut interpreter = crate::Interpreter::build(std::io::empty(), std::io::sink()).build();
+	let result = interpreter.run(&stream);
+	assert!(matches!(result, Err(Error::Underflow)));
+	let span = spans[interpreter.instruction_pointer()];
+	assert_eq!((span.start.line, span.start.column), (2, 3));
+}
+
+#[test]
+fn bracket_positions() {
+	use crate::compile::Error;
+	use crate::span::Position;
+
+	let error = crate::compile::<u8>("+[\n  [->+<\n").unwrap_err();
+	assert_eq!(
+		error,
+		Error::UnmatchedStart(vec![
+			Position {
+				offset: 1,
+				line: 1,
+				column: 2
+			},
+			Position {
+				offset: 5,
+				line: 2,
+				column: 3
+			},
+		])
+	);
+	assert_eq!(error.to_string(), "unmatched loop start at 1:2, 2:3");
+
+	let error = crate::compile::<u8>("[]\n]").unwrap_err();
+	assert_eq!(error.to_string(), "unmatched loop end at 2:1");
+}
+
+#[test]
+fn lints() {
+	use crate::lint::{lint, Kind};
+
+	let kinds = |code: &str| {
+		lint::<u8>(code.as_bytes())
+			.unwrap()
+			.into_iter()
+			.map(|warning| (warning.kind, warning.span.range()))
+			.collect::<Vec<_>>()
+	};
+
+	assert_eq!(kinds("[a comment]++[->+<]>."), []);
+	assert_eq!(
+		kinds("[a comment, sort of.]+-><"),
+		[
+			(Kind::CommentLoopInstructions, 0..21),
+			(Kind::CancellingPair, 21..23),
+			(Kind::CancellingPair, 23..25),
+		]
+	);
+	assert_eq!(kinds(",[-][+-]"), [(Kind::UnreachableLoop, 4..8)]);
+	assert_eq!(
+		kinds("+[.]>+\n."),
+		[(Kind::InfiniteLoop, 1..4), (Kind::UnreachableCode, 4..8)]
+	);
+	// the outer loop might be skipped, so only the rest of its body never runs
+	assert_eq!(kinds(",[[-]+[]]+."), [(Kind::InfiniteLoop, 6..8)]);
+	assert_eq!(
+		kinds(",[[-]+[].]+."),
+		[(Kind::InfiniteLoop, 6..8), (Kind::UnreachableCode, 8..9)]
+	);
+	assert_eq!(
+		kinds("+[[-]+[].]+."),
+		[(Kind::InfiniteLoop, 6..8), (Kind::UnreachableCode, 8..12)]
+	);
+	assert_eq!(kinds(">>.<<<"), [(Kind::PointerUnderflow, 5..6)]);
+	// the loop might leave the pointer anywhere
+	assert_eq!(kinds(",[>]<<"), []);
+	// 256 wraps around to 0 in 8-bit mode
+	assert_eq!(
+		kinds(&format!("{}[]", "+".repeat(256))),
+		[(Kind::UnreachableLoop, 256..258)]
+	);
+}
+
+#[test]
+fn pointer_range() {
+	use crate::compile::PointerRange;
+
+	let range = |code: &str| {
+		crate::InstructionStream::<u8>::from_code(code.bytes())
+			.unwrap()
+			.pointer_range()
+	};
+
+	assert_eq!(range(">>>.<<"), PointerRange::Bounded { min: 0, max: 3 });
+	assert_eq!(
+		range("+[->>+<<]<"),
+		PointerRange::Bounded { min: -1, max: 2 }
+	);
+	assert_eq!(range(",[>]"), PointerRange::Unbounded);
+	assert_eq!(range(",[>+<<]"), PointerRange::Unbounded);
+
+	// the same ranges survive optimization into offsets, multiplications and scans
+	let optimized = crate::compile::<u8>(",[->>+<<]>>>>>>.").unwrap();
+	assert_eq!(
+		optimized.pointer_range(),
+		PointerRange::Bounded { min: 0, max: 6 }
+	);
+	let optimized = crate::compile::<u8>(",[>>]").unwrap();
+	assert_eq!(optimized.pointer_range(), PointerRange::Unbounded);
+
+	let wide = crate::compile::<u8>(&format!(",{}.", ">".repeat(40_000))).unwrap();
+	assert_eq!(wide.recommended_array_size(), 40_001);
+	// the old estimate summed every move, even though each iteration comes back
+	let looping = crate::compile::<u8>(&format!(
+		",[{}+{}-]",
+		">".repeat(20_000),
+		"<".repeat(20_000)
+	))
+	.unwrap();
+	assert_eq!(looping.recommended_array_size(), crate::MIN_DATA_ARRAY_SIZE);
+}
+
+#[test]
+fn bytecode() {
+	use crate::compile::bytecode::Error;
+	use crate::{Instruction, InstructionStream};
+
+	let code = ",[->+>---<<]>>[>]<.+++[-]>>,<<.";
+	let stream = crate::compile::<u16>(code).unwrap();
+	let mut bytes = Vec::new();
+	stream.write_bytecode(&mut bytes).unwrap();
+	assert_eq!(crate::compile::bytecode::cell_width(&bytes), Ok(2));
+
+	let loaded = InstructionStream::<u16>::from_bytecode(&bytes).unwrap();
+	assert_eq!(loaded.instructions(), stream.instructions());
+	assert_eq!(loaded.pointer_range(), stream.pointer_range());
+
+	assert_eq!(
+		InstructionStream::<u8>::from_bytecode(&bytes).unwrap_err(),
+		Error::CellWidth {
+			expected: 1,
+			found: 2
+		}
+	);
+	assert_eq!(
+		InstructionStream::<u16>::from_bytecode(code.as_bytes()).unwrap_err(),
+		Error::NotBytecode
+	);
+	assert_eq!(
+		InstructionStream::<u16>::from_bytecode(&bytes[..bytes.len() - 1]).unwrap_err(),
+		Error::Checksum
+	);
+	let mut corrupted = bytes.clone();
+	corrupted[12] ^= 1;
+	assert_eq!(
+		InstructionStream::<u16>::from_bytecode(&corrupted).unwrap_err(),
+		Error::Checksum
+	);
+
+	// a well-formed file whose loop start jumps to the wrong place
+	let stream = InstructionStream::<u8>::new(vec![
+		Instruction::Read,
+		Instruction::LoopStart(0),
+		Instruction::Write,
+		Instruction::LoopEnd(0),
+	])
+	.unwrap();
+	let mut bytes = Vec::new();
+	stream.write_bytecode(&mut bytes).unwrap();
+	let loop_start = bytes.iter().position(|&byte| byte == 3).unwrap();
+	bytes[loop_start + 1] = 2;
+	let len = bytes.len();
+	let checksum = crate::compile::bytecode::crc32(&bytes[..len - 4]);
+	bytes[len - 4..].copy_from_slice(&checksum.to_le_bytes());
+	assert_eq!(
+		InstructionStream::<u8>::from_bytecode(&bytes).unwrap_err(),
+		Error::InvalidJump(1)
+	);
+}
+
 fn generate_random_code() -> String {
 	const NUM_SECTIONS: usize = 20;
 	const NON_LOOP_CHARS: &[u8] = b"+-<>.."; // `.` is doubled to have a higher probability
@@ -72,6 +478,1116 @@ fn generate_random_code() -> String {
 	ret
 }
 
+/// Compile and optimize `code`, and render it with `render`.
+fn render<T: crate::CellType>(
+	code: &str,
+	render: impl FnOnce(&crate::InstructionStream<T>, &mut Vec<u8>) -> std::io::Result<()>,
+) -> String {
+	let stream = crate::compile::<T>(code).unwrap();
+	let mut out = Vec::new();
+	render(&stream, &mut out).unwrap();
+	String::from_utf8(out).unwrap()
+}
+
+const HELLO: &str = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+
+/// Programs that don't depend on the size of cells, with their input and output.
+const SAMPLES: &[(&str, &[u8], &[u8])] = &[
+	(HELLO, b"", b"Hello World!\n"),
+	(",[.,]", b"cat", b"cat"),
+	(">,[>,]<[.<]", b"desserts", b"stressed"),
+	// multiplies the two input bytes
+	(",>,<[->[->+>+<<]>>[-<<+>>]<<<]>>.", b"\x07\x09", b"\x3f"),
+	("+>+>+>>+<<<<[>]+++++++++[-<++++++++>]<.", b"", b"I"),
+	(">>+>>+>>>+<<<[>>]+[<<]>>>>>>.", b"", b"\x01"),
+];
+
+/// Writes whether a cell can hold 256, which depends on the size of cells.
+const WIDE_SAMPLE: &str = ">++++++++[<++++++++>-]<[>++++<-]>[[-]<+>]<.";
+
+/// A backend that can render and run programs, if the tools it needs are installed.
+trait Backend {
+	/// Render `stream` into `dir`, run it with `input`, and return its output, or `None` if a tool it needs isn't installed.
+	fn run<T: crate::CellType>(
+		&self,
+		stream: &crate::InstructionStream<T>,
+		dir: &std::path::Path,
+		input: &[u8],
+	) -> Option<Vec<u8>>;
+}
+
+/// Check that `backend` gives the right output for the sample programs, unless the tools it needs aren't installed.
+fn check_backend(backend: &impl Backend) {
+	fn check<T: crate::CellType>(
+		backend: &impl Backend,
+		dir: &std::path::Path,
+		code: &str,
+		input: &[u8],
+		expected: &[u8],
+	) -> Option<()> {
+		let mut stream = crate::InstructionStream::<T>::from_code(code.bytes()).unwrap();
+		stream
+			.optimize_with(&mut crate::compile::OptimizeOptions::level(3))
+			.unwrap();
+		let output = backend.run(&stream, dir, input)?;
+		assert_eq!(output, expected, "{code:?} with {}-bit cells", 8 * T::BYTES);
+		Some(())
+	}
+
+	let dir = std::env::temp_dir().join(format!(
+		"bfirs-test-{}-{}",
+		std::any::type_name_of_val(backend)
+			.rsplit("::")
+			.next()
+			.unwrap(),
+		std::process::id()
+	));
+	std::fs::create_dir_all(&dir).unwrap();
+	let checked = SAMPLES
+		.iter()
+		.try_for_each(|&(code, input, expected)| check::<u8>(backend, &dir, code, input, expected))
+		.and_then(|()| check::<u8>(backend, &dir, WIDE_SAMPLE, b"", b"\0"))
+		.and_then(|()| check::<u16>(backend, &dir, WIDE_SAMPLE, b"", b"\x01"))
+		.and_then(|()| check::<u32>(backend, &dir, WIDE_SAMPLE, b"", b"\x01"));
+	std::fs::remove_dir_all(&dir).unwrap();
+	if checked.is_none() {
+		eprintln!("skipped running the rendered programs, since a tool isn't installed");
+	}
+}
+
+/// Run `program` with `args` in `dir`, giving it `input`.
+///
+/// Returns `None` if `program` isn't installed.
+fn run_tool(
+	dir: &std::path::Path,
+	program: &str,
+	args: &[&str],
+	input: &[u8],
+) -> Option<std::process::Output> {
+	use std::io::Write as _;
+	use std::process::{Command, Stdio};
+
+	let mut child = loop {
+		match Command::new(program)
+			.args(args)
+			.current_dir(dir)
+			.stdin(Stdio::piped())
+			.stdout(Stdio::piped())
+			.stderr(Stdio::piped())
+			.spawn()
+		{
+			Err(error) if error.kind() == std::io::ErrorKind::NotFound => return None,
+			// a process that another test is starting can briefly hold a program that was just written open
+			Err(error) if error.kind() == std::io::ErrorKind::ExecutableFileBusy => {
+				std::thread::sleep(std::time::Duration::from_millis(10));
+			}
+			child => break child.unwrap(),
+		}
+	};
+	child.stdin.take().unwrap().write_all(input).unwrap();
+	Some(child.wait_with_output().unwrap())
+}
+
+/// The output of a tool that must have succeeded.
+fn succeeded(program: &str, output: std::process::Output) -> Vec<u8> {
+	assert!(
+		output.status.success(),
+		"{program} failed: {}",
+		String::from_utf8_lossy(&output.stderr)
+	);
+	output.stdout
+}
+
+struct RustBackend;
+
+impl Backend for RustBackend {
+	fn run<T: crate::CellType>(
+		&self,
+		stream: &crate::InstructionStream<T>,
+		dir: &std::path::Path,
+		input: &[u8],
+	) -> Option<Vec<u8>> {
+		let mut source = Vec::new();
+		stream
+			.render_rust(&mut source, crate::compile::RenderRustOptions::default())
+			.unwrap();
+		std::fs::write(dir.join("program.rs"), source).unwrap();
+		let args = ["--edition", "2021", "-o", "program", "program.rs"];
+		succeeded("rustc", run_tool(dir, "rustc", &args, b"")?);
+		Some(succeeded(
+			"program",
+			run_tool(dir, "./program", &[], input)?,
+		))
+	}
+}
+
+struct JsBackend;
+
+impl Backend for JsBackend {
+	fn run<T: crate::CellType>(
+		&self,
+		stream: &crate::InstructionStream<T>,
+		dir: &std::path::Path,
+		input: &[u8],
+	) -> Option<Vec<u8>> {
+		let mut module = Vec::new();
+		stream.render_js(&mut module).unwrap();
+		std::fs::write(dir.join("program.mjs"), module).unwrap();
+		std::fs::write(
+			dir.join("main.mjs"),
+			"import { readFileSync } from \"node:fs\";\nimport { run } from \"./program.mjs\";\nprocess.stdout.write(run(readFileSync(0)));\n",
+		)
+		.unwrap();
+		Some(succeeded(
+			"node",
+			run_tool(dir, "node", &["main.mjs"], input)?,
+		))
+	}
+}
+
+struct LlvmBackend;
+
+impl Backend for LlvmBackend {
+	fn run<T: crate::CellType>(
+		&self,
+		stream: &crate::InstructionStream<T>,
+		dir: &std::path::Path,
+		input: &[u8],
+	) -> Option<Vec<u8>> {
+		let mut module = Vec::new();
+		stream.render_llvm(&mut module).unwrap();
+		std::fs::write(dir.join("program.ll"), module).unwrap();
+		let output = run_tool(dir, "lli", &["program.ll"], input)?;
+		if output.status.success() {
+			return Some(output.stdout);
+		}
+		// before LLVM 15, opaque pointers have to be enabled
+		let args = ["-opaque-pointers", "program.ll"];
+		Some(succeeded("lli", run_tool(dir, "lli", &args, input)?))
+	}
+}
+
+struct PythonBackend;
+
+impl Backend for PythonBackend {
+	fn run<T: crate::CellType>(
+		&self,
+		stream: &crate::InstructionStream<T>,
+		dir: &std::path::Path,
+		input: &[u8],
+	) -> Option<Vec<u8>> {
+		let mut script = Vec::new();
+		stream.render_python(&mut script).unwrap();
+		std::fs::write(dir.join("program.py"), script).unwrap();
+		Some(succeeded(
+			"python3",
+			run_tool(dir, "python3", &["program.py"], input)?,
+		))
+	}
+}
+
+struct AsmBackend;
+
+impl Backend for AsmBackend {
+	fn run<T: crate::CellType>(
+		&self,
+		stream: &crate::InstructionStream<T>,
+		dir: &std::path::Path,
+		input: &[u8],
+	) -> Option<Vec<u8>> {
+		if !cfg!(all(target_os = "linux", target_arch = "x86_64")) {
+			return None;
+		}
+		let mut assembly = Vec::new();
+		stream
+			.render_asm(
+				&mut assembly,
+				crate::compile::RenderAsmOptions::default().bounds_checks(true),
+			)
+			.unwrap();
+		std::fs::write(dir.join("program.s"), assembly).unwrap();
+		succeeded(
+			"as",
+			run_tool(dir, "as", &["-o", "program.o", "program.s"], b"")?,
+		);
+		succeeded(
+			"ld",
+			run_tool(dir, "ld", &["-o", "program", "program.o"], b"")?,
+		);
+		Some(succeeded(
+			"program",
+			run_tool(dir, "./program", &[], input)?,
+		))
+	}
+}
+
+struct ElfBackend;
+
+impl Backend for ElfBackend {
+	fn run<T: crate::CellType>(
+		&self,
+		stream: &crate::InstructionStream<T>,
+		dir: &std::path::Path,
+		input: &[u8],
+	) -> Option<Vec<u8>> {
+		if !cfg!(all(target_os = "linux", target_arch = "x86_64")) {
+			return None;
+		}
+		let mut elf = Vec::new();
+		stream
+			.write_elf(
+				&mut elf,
+				crate::compile::RenderAsmOptions::default().bounds_checks(true),
+			)
+			.unwrap();
+		let path = dir.join("program");
+		std::fs::write(&path, elf).unwrap();
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::PermissionsExt as _;
+
+			std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+		}
+		Some(succeeded(
+			"program",
+			run_tool(dir, "./program", &[], input)?,
+		))
+	}
+}
+
+#[test]
+fn render_bf() {
+	use crate::compile::RenderBfOptions;
+
+	let render = |code: &str, options| {
+		let mut stream = crate::InstructionStream::<u8>::from_code(code.bytes()).unwrap();
+		stream
+			.optimize_with(&mut crate::compile::OptimizeOptions::level(3))
+			.unwrap();
+		let mut out = Vec::new();
+		stream.render_bf(&mut out, options).unwrap();
+		String::from_utf8(out).unwrap()
+	};
+
+	assert_eq!(
+		render(",[->+>---<<]>>.", RenderBfOptions::default()),
+		",[->+>---<<]>>.\n"
+	);
+	assert_eq!(
+		// the output is known, so it's written with the current cell before the cells are set
+		render("+++.>++.", RenderBfOptions::default()),
+		"+++.-.--+++>++\n"
+	);
+	assert_eq!(
+		render(
+			",>>++++++.<<.",
+			RenderBfOptions::default()
+				.line_width(Some(4))
+				.run_length_comments(true)
+		),
+		",>>+\n++++\n+6.<\n<.\n"
+	);
+
+	// large amounts are built with multiplication loops instead of runs
+	let code = "++++++++[>++++++++<-]>[>++++++++<-]>[>++++++++<-]>[>+++<-]>+.,[>+<-]>.";
+	let mut stream = crate::InstructionStream::<u32>::from_code(code.bytes()).unwrap();
+	stream
+		.optimize_with(&mut crate::compile::OptimizeOptions::level(3))
+		.unwrap();
+	let mut rendered = Vec::new();
+	stream
+		.render_bf(&mut rendered, RenderBfOptions::default())
+		.unwrap();
+	assert!(rendered.len() < 2 * code.len(), "{rendered:?}");
+	let run = |code: &[u8]| {
+		let stream = crate::InstructionStream::<u32>::from_code(code.iter().copied()).unwrap();
+		let mut out = Vec::new();
+		crate::Interpreter::build(&b"a"[..], &mut out)
+			.build()
+			.run(&stream)
+			.unwrap();
+		out
+	};
+	assert_eq!(run(&rendered), run(code.as_bytes()));
+
+	for _ in 0..100 {
+		let code = generate_random_code();
+		let Ok(expected) = run_output(&code, true) else {
+			continue;
+		};
+		let rendered = render(&code, RenderBfOptions::default());
+		assert_eq!(
+			run_output(&rendered, false),
+			Ok(expected),
+			"rendering {code:?} as {rendered:?}"
+		);
+	}
+}
+
+#[test]
+fn render_bf_mul_adds() {
+	use std::num::NonZeroU8;
+
+	use crate::compile::RenderBfOptions;
+	use crate::Instruction as I;
+
+	// the optimizer always follows `MulAdd`s with a `Set`, but hand-built streams don't have to
+	let two = NonZeroU8::new(2).unwrap();
+	let three = NonZeroU8::new(3).unwrap();
+	let streams = [
+		vec![I::Read, I::MulAdd(1, two), I::Write, I::WriteAt(1)],
+		vec![I::Read, I::MulAdd(1, two)],
+		vec![
+			I::Read,
+			I::MulAdd(1, two),
+			I::MulAdd(2, three),
+			I::AddAt(1, three),
+			I::WriteAt(1),
+			I::WriteAt(2),
+			I::Write,
+		],
+		// every scratch cell nearby has a value, which the spare cell has to get back
+		vec![
+			I::SetAt(1, 5),
+			I::SetAt(2, 6),
+			I::SetAt(3, 7),
+			I::SetAt(4, 8),
+			I::SetAt(5, 9),
+			I::SetAt(6, 10),
+			I::SetAt(7, 11),
+			I::SetAt(8, 12),
+			I::Read,
+			I::MulAdd(1, three),
+			I::Write,
+			I::WriteAt(1),
+			I::WriteAt(2),
+		],
+	];
+	let run = |stream: &crate::InstructionStream<u8>| {
+		let mut out = Vec::new();
+		crate::Interpreter::build(&b"\x05"[..], &mut out)
+			.build()
+			.run(stream)
+			.unwrap();
+		out
+	};
+	for instructions in streams {
+		let stream = crate::InstructionStream::<u8>::new(instructions).unwrap();
+		let mut rendered = Vec::new();
+		stream
+			.render_bf(&mut rendered, RenderBfOptions::default())
+			.unwrap();
+		let rendered_stream =
+			crate::InstructionStream::<u8>::from_code(rendered.iter().copied()).unwrap();
+		assert_eq!(
+			run(&rendered_stream),
+			run(&stream),
+			"rendering {:?} as {:?}",
+			stream.instructions(),
+			String::from_utf8_lossy(&rendered)
+		);
+	}
+}
+
+#[test]
+fn render_rust() {
+	use crate::compile::RenderRustOptions;
+
+	let program = render::<u16>(",[.,]", |stream, out| {
+		stream.render_rust(out, RenderRustOptions::default())
+	});
+	assert!(program.starts_with("#![forbid(unsafe_code)]\n"));
+	assert!(program.contains("type Cell = u16;\n"));
+	assert!(program.contains("\twhile tape.cells[tape.ptr] != 0 {\n"));
+	assert!(program.contains("write(output, &[tape.cells[tape.ptr] as u8])?;"));
+	assert!(program.contains("\nfn main() {\n"));
+
+	let library = render::<u16>(",[.,]", |stream, out| {
+		stream.render_rust(out, RenderRustOptions::default().library(true))
+	});
+	assert!(
+		library.contains("pub fn run(input: impl Read, output: impl Write) -> Result<(), Error> {\n")
+	);
+	assert!(!library.contains("fn main"));
+
+	// without a data array, only what's used is rendered
+	let empty = render::<u8>("", |stream, out| {
+		stream.render_rust(out, RenderRustOptions::default())
+	});
+	assert!(!empty.contains("struct Tape"));
+	assert!(!empty.contains("type Cell"));
+
+	check_backend(&RustBackend);
+}
+
+#[test]
+fn render_js() {
+	let module = render::<u32>(",[-.,]", |stream, out| stream.render_js(out));
+	assert!(module.contains("export class RuntimeError extends Error {\n"));
+	assert!(module.contains("export function run(input) {\n\tconst tape = new Uint32Array(30000);\n"));
+	assert!(module.contains("\twhile (tape[p] !== 0) {\n\t\ttape[p] -= 1;\n"));
+	assert!(module.contains("\toutput.push(tape[p] & 255);\n"));
+	assert!(module.ends_with("\treturn Uint8Array.from(output);\n}\n"));
+
+	check_backend(&JsBackend);
+}
+
+#[test]
+fn wasm() {
+	fn leb(bytes: &mut &[u8]) -> u32 {
+		let mut value = 0;
+		for shift in (0..35).step_by(7) {
+			let (&byte, rest) = bytes.split_first().unwrap();
+			*bytes = rest;
+			value |= u32::from(byte & 0x7f) << shift;
+			if byte & 0x80 == 0 {
+				return value;
+			}
+		}
+		panic!("LEB128 is too long");
+	}
+
+	fn name<'b>(bytes: &mut &'b [u8]) -> &'b str {
+		let len = leb(bytes) as usize;
+		let (name, rest) = bytes.split_at(len);
+		*bytes = rest;
+		std::str::from_utf8(name).unwrap()
+	}
+
+	let stream = crate::InstructionStream::<u16>::from_code(",[->+<]>.".bytes()).unwrap();
+	let mut module = Vec::new();
+	stream.write_wasm(&mut module).unwrap();
+
+	let mut bytes = module.strip_prefix(b"\0asm\x01\0\0\0").unwrap();
+	let mut sections = Vec::new();
+	while let Some((&id, rest)) = bytes.split_first() {
+		bytes = rest;
+		let len = leb(&mut bytes) as usize;
+		let (contents, rest) = bytes.split_at(len);
+		sections.push((id, contents));
+		bytes = rest;
+	}
+	assert_eq!(
+		sections.iter().map(|&(id, _)| id).collect::<Vec<_>>(),
+		[1, 2, 3, 5, 7, 10]
+	);
+
+	let mut imports = sections[1].1;
+	assert_eq!(leb(&mut imports), 2);
+	for expected in ["read", "write"] {
+		assert_eq!(name(&mut imports), "env");
+		assert_eq!(name(&mut imports), expected);
+		assert_eq!(imports[0], 0, "imports a function");
+		imports = &imports[2..];
+	}
+	assert!(imports.is_empty());
+
+	// 30000 two-byte cells take one page
+	assert_eq!(sections[3].1, [1, 0, 1]);
+
+	let mut exports = sections[4].1;
+	assert_eq!(leb(&mut exports), 2);
+	assert_eq!(name(&mut exports), "run");
+	assert_eq!(exports[..2], [0, 2]);
+	exports = &exports[2..];
+	assert_eq!(name(&mut exports), "memory");
+	assert_eq!(exports, [2, 0]);
+
+	let mut code = sections[5].1;
+	assert_eq!(leb(&mut code), 1);
+	assert_eq!(leb(&mut code) as usize, code.len());
+	// the body ends by returning a status of 0
+	assert!(code.ends_with(&[0x41, 0, 0x0b]));
+}
+
+#[test]
+fn render_llvm() {
+	let cat = render::<u8>(",[.,]", |stream, out| stream.render_llvm(out));
+	assert!(cat.starts_with("@tape = internal global [30000 x i8] zeroinitializer\n"));
+	assert!(cat.contains("declare i32 @getchar()\ndeclare i32 @putchar(i32)\n"));
+	assert!(cat.contains("define i32 @main() {\nentry:\n  %p = alloca i64\n  store i64 0, ptr %p\n"));
+	assert!(cat.contains("label %loop1.end, label %loop1\nloop1:\n"));
+	assert!(cat.contains("label %loop1.end, label %loop1\nloop1.end:\n"));
+	assert!(cat.ends_with("  ret i32 0\n}\n"));
+
+	let wide = render::<u16>(",-.<,-.", |stream, out| stream.render_llvm(out));
+	// cells left of the start are in the array
+	assert!(wide.starts_with("@tape = internal global [30001 x i16] zeroinitializer\n"));
+	assert!(wide.contains("  store i64 1, ptr %p\n"));
+	assert!(wide.contains(" = sub i16 "));
+	assert!(wide.contains(" = zext i16 "));
+
+	let scan = render::<u32>("+[>]", |stream, out| stream.render_llvm(out));
+	assert!(scan.contains("  br label %scan1\nscan1:\n"));
+	assert!(!scan.contains("zext"));
+
+	check_backend(&LlvmBackend);
+}
+
+#[test]
+fn render_python() {
+	let cat = render::<u8>(",[.,]", |stream, out| stream.render_python(out));
+	assert!(cat.starts_with("#!/usr/bin/env python3\nimport sys\n\nTAPE_LEN = 30000\nMASK = 0xff\n"));
+	assert!(cat.contains("\ttape = bytearray(TAPE_LEN)\n"));
+	assert!(cat
+		.contains("\twhile tape[p]:\n\t\twrite(bytes((tape[p],)))\n\t\ttape[p] = read()\n\tflush()\n"));
+	assert!(cat.ends_with("\t\trun(sys.stdin.buffer, sys.stdout.buffer)\n\texcept Error as error:\n\t\tprint(f\"error: {error}\", file=sys.stderr)\n\t\tsys.exit(1)\n"));
+
+	let wide = render::<u16>(",-.>+[]", |stream, out| stream.render_python(out));
+	assert!(wide.contains("from array import array\n"));
+	assert!(wide.contains("\ttape = array(\"H\", [0]) * TAPE_LEN\n"));
+	assert!(wide.contains("\ttape[p] = (tape[p] - 1) & MASK\n"));
+	assert!(wide.contains("\twrite(bytes((tape[p] & 0xff,)))\n"));
+	// empty loops still need a body
+	assert!(wide.contains("\twhile tape[p]:\n\t\tpass\n"));
+	let long = render::<u32>(",.", |stream, out| stream.render_python(out));
+	assert!(long.contains("\ttape = array(\"L\", [0]) * TAPE_LEN\n"));
+
+	// python can't nest that many loops in one function
+	let nested = render::<u8>(
+		&format!(",{}.{}", "[,".repeat(25), "]".repeat(25)),
+		|stream, out| stream.render_python(out),
+	);
+	assert_eq!(nested.matches("def loop").count(), 1);
+	let body = nested.split("def loop").nth(1).unwrap();
+	assert!(body.starts_with("39(p):\n"));
+	assert!(body.contains("return p\n"));
+	assert!(body.contains("p = loop39(p)\n"));
+
+	check_backend(&PythonBackend);
+}
+
+#[test]
+fn render_asm() {
+	use crate::compile::RenderAsmOptions;
+
+	let unchecked = render::<u8>(",>.", |stream, out| {
+		stream.render_asm(out, RenderAsmOptions::default())
+	});
+	assert!(unchecked.contains("\t.globl _start\n"));
+	assert!(unchecked.contains("bf_tape:\n\t.skip 30000\n"));
+	assert!(unchecked.contains("\tmovb %al, (%r14,%rbx)\n"));
+	assert!(!unchecked.contains("jae bf_overflow"));
+
+	let checked = render::<u16>(",[,>]<<", |stream, out| {
+		stream.render_asm(out, RenderAsmOptions::default().bounds_checks(true))
+	});
+	assert!(checked.contains("bf_tape:\n\t.skip 60000\n"));
+	assert!(checked.contains("\tmovw %ax, (%r14,%rbx)\n"));
+	assert!(checked.contains("\taddq $2, %rbx\n\tcmpq %r15, %rbx\n\tjae bf_overflow\n"));
+	assert!(checked.contains("\tsubq $4, %rbx\n\tjb bf_underflow\n"));
+
+	let wide = render::<u32>(",[.-]", |stream, out| {
+		stream.render_asm(out, RenderAsmOptions::default())
+	});
+	assert!(wide.contains("\tcmpl $0, (%r14,%rbx)\n"));
+
+	check_backend(&AsmBackend);
+}
+
+#[test]
+fn write_elf() {
+	use crate::compile::RenderAsmOptions;
+
+	let u16_at = |bytes: &[u8], at: usize| u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap());
+	let u32_at = |bytes: &[u8], at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+	let u64_at = |bytes: &[u8], at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+
+	let stream = crate::InstructionStream::<u8>::from_code(",[.,]".bytes()).unwrap();
+	let mut elf = Vec::new();
+	stream
+		.write_elf(&mut elf, RenderAsmOptions::default().bounds_checks(true))
+		.unwrap();
+
+	assert!(elf.starts_with(b"\x7fELF\x02\x01\x01"));
+	// an x86-64 executable
+	assert_eq!(u16_at(&elf, 16), 2);
+	assert_eq!(u16_at(&elf, 18), 0x3e);
+	let entry = u64_at(&elf, 24);
+	let program_headers = usize::try_from(u64_at(&elf, 32)).unwrap();
+	assert_eq!(u16_at(&elf, 54), 56);
+	let segments: Vec<_> = (0..u16_at(&elf, 56))
+		.map(|idx| program_headers + usize::from(idx) * 56)
+		.filter(|&at| u32_at(&elf, at) == 1)
+		.map(|at| {
+			(
+				u32_at(&elf, at + 4),
+				u64_at(&elf, at + 16),
+				u64_at(&elf, at + 32),
+				u64_at(&elf, at + 40),
+			)
+		})
+		.collect();
+
+	// the file is loaded as readable and executable, and the entry point is in it
+	let (flags, address, file_len, memory_len) = segments[0];
+	assert_eq!(flags, 0b101);
+	assert_eq!(file_len, elf.len() as u64);
+	assert_eq!(memory_len, file_len);
+	assert!((address..address + file_len).contains(&entry));
+	// the data array is readable and writable, after the file
+	let (flags, bss_address, file_len, memory_len) = segments[1];
+	assert_eq!(flags, 0b110);
+	assert_eq!(file_len, 0);
+	assert!(memory_len >= 30000);
+	assert!(bss_address >= address + elf.len() as u64);
+
+	// execution starts by loading the address of the data array into r14
+	let entry = usize::try_from(entry - address).unwrap();
+	assert_eq!(elf[entry..entry + 3], [0x4c, 0x8d, 0x35]);
+	let displacement = i32::from_le_bytes(elf[entry + 3..entry + 7].try_into().unwrap());
+	let tape = (entry + 7) as u64 + address;
+	assert_eq!(
+		tape.checked_add_signed(displacement.into()),
+		Some(bss_address)
+	);
+
+	check_backend(&ElfBackend);
+}
+
+#[cfg(feature = "jit")]
+#[test]
+fn jit() {
+	use crate::{CellType, InstructionStream, Interpreter};
+
+	struct BrokenPipe;
+
+	impl std::io::Write for BrokenPipe {
+		fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+			Err(std::io::ErrorKind::BrokenPipe.into())
+		}
+
+		fn flush(&mut self) -> std::io::Result<()> {
+			Ok(())
+		}
+	}
+
+	/// Compare running with and without compiling, unless the interpreter doesn't finish.
+	fn compare<T: CellType>(code: &str, input: &[u8]) {
+		let stream = InstructionStream::<T>::optimized_from_code(code.bytes()).unwrap();
+		let mut expected_output = Vec::new();
+		let mut interpreter = Interpreter::build(input, &mut expected_output)
+			.configure_for(&stream)
+			.instruction_limit(1_000_000)
+			.build();
+		let expected = interpreter.run(&stream);
+		if expected == Err(Error::NotEnoughInstructions) {
+			return;
+		}
+		let expected_state = (
+			interpreter.data_pointer(),
+			interpreter.instruction_pointer(),
+			interpreter.into_data(),
+		);
+
+		let mut output = Vec::new();
+		let mut jit = Interpreter::build(input, &mut output)
+			.configure_for(&stream)
+			.build();
+		assert_eq!(jit.run_jit(&stream), expected, "running {code:?}");
+		let state = (
+			jit.data_pointer(),
+			jit.instruction_pointer(),
+			jit.into_data(),
+		);
+		assert_eq!(state, expected_state, "running {code:?}");
+		assert_eq!(output, expected_output, "running {code:?}");
+	}
+
+	compare::<u8>(",[.,]", b"cat");
+	compare::<u16>("+[>+]", b"");
+	compare::<u32>(">>+[<<]", b"");
+	compare::<u8>("+++[>+++[>+++<-]<-]>>[<<+>>-]<<<", b"");
+	for _ in 0..100 {
+		let code = generate_random_code().replacen("..", ",", 3);
+		compare::<u8>(&code, b"input");
+		compare::<u16>(&code, b"input");
+		compare::<u32>(&code, b"input");
+	}
+
+	let stream = crate::compile::<u8>("++.>.").unwrap();
+	let mut jit = Interpreter::build(std::io::empty(), BrokenPipe)
+		.configure_for(&stream)
+		.build();
+	assert_eq!(
+		jit.run_jit(&stream),
+		Err(Error::OutputIo(std::io::ErrorKind::BrokenPipe.into()))
+	);
+	assert_eq!(jit.instruction_pointer(), 1);
+}
+
+#[test]
+fn format() {
+	use crate::format::{format, lex, FormatOptions};
+
+	let source =
+		"[ a comment loop.\n  with lines ]\n\n\n+++ set to 3   \r\n>>> move  [->+<]  x ++ \n[ body\n-]";
+	let formatted = format(source.as_bytes(), &FormatOptions::default()).unwrap();
+	assert_eq!(
+		String::from_utf8(formatted).unwrap(),
+		"[ a comment loop\n  .\n  with lines\n]\n\n+++ set to 3\n>>> move\n[->+<] x\n++\n[ body\n  -\n]\n"
+	);
+
+	let fragments = [
+		"+",
+		"-",
+		"<",
+		">",
+		".",
+		",",
+		"[",
+		"]",
+		" ",
+		"\n",
+		"\n\n",
+		"\t",
+		"word",
+		"a, b",
+		"++++++++++++++++",
+	];
+	let instructions = |code: &[u8]| {
+		code
+			.iter()
+			.copied()
+			.filter(|byte| b"+-<>.,[]".contains(byte))
+			.collect::<Vec<_>>()
+	};
+	for _ in 0..500 {
+		let mut source = String::new();
+		let mut depth = 0;
+		for _ in 0..rand::thread_rng().gen_range(0..60) {
+			let fragment =
+				*rand::seq::SliceRandom::choose(&fragments[..], &mut rand::thread_rng()).unwrap();
+			match fragment {
+				"[" => depth += 1,
+				"]" if depth == 0 => continue,
+				"]" => depth -= 1,
+				_ => {}
+			}
+			source.push_str(fragment);
+		}
+		source.push_str(&"]".repeat(depth));
+
+		assert_eq!(
+			lex(source.as_bytes())
+				.flat_map(|token| token.text)
+				.copied()
+				.collect::<Vec<_>>(),
+			source.as_bytes()
+		);
+
+		let options = FormatOptions::default().line_width(20);
+		let formatted = format(source.as_bytes(), &options).unwrap();
+		assert_eq!(
+			instructions(&formatted),
+			instructions(source.as_bytes()),
+			"formatting {source:?}"
+		);
+		assert_eq!(
+			format(&formatted, &options).unwrap(),
+			formatted,
+			"formatting {source:?}"
+		);
+	}
+}
+
+#[test]
+fn program() {
+	use crate::program::Error;
+	use crate::{Instruction, Program};
+
+	const VALID: &[Instruction<u8>] = &[
+		Instruction::LoopStart(5),
+		Instruction::<u8>::inc(1),
+		Instruction::LoopStart(3),
+		Instruction::LoopEnd(2),
+		Instruction::dec_ptr(2),
+		Instruction::LoopEnd(0),
+		Instruction::LoopStart(7),
+		Instruction::LoopEnd(6),
+	];
+	const PROGRAM: Program<'static, u8> = Program::expect_valid(VALID);
+	assert_eq!(PROGRAM.instructions(), VALID);
+
+	let invalid: &[(&[Instruction<u8>], Error)] = &[
+		(
+			&[Instruction::LoopStart(2), Instruction::LoopEnd(0)],
+			Error::OutOfRange(0),
+		),
+		(&[Instruction::LoopEnd(0)], Error::Mismatched(0)),
+		(
+			&[Instruction::Write, Instruction::LoopEnd(9)],
+			Error::OutOfRange(1),
+		),
+		(
+			&[Instruction::LoopStart(1), Instruction::LoopEnd(1)],
+			Error::Mismatched(0),
+		),
+		// crossing loops
+		(
+			&[
+				Instruction::LoopStart(2),
+				Instruction::LoopStart(3),
+				Instruction::LoopEnd(0),
+				Instruction::LoopEnd(1),
+			],
+			Error::Mismatched(1),
+		),
+		// a stray loop end inside a loop
+		(
+			&[
+				Instruction::LoopStart(3),
+				Instruction::LoopEnd(0),
+				Instruction::Write,
+				Instruction::LoopEnd(0),
+			],
+			Error::Mismatched(1),
+		),
+	];
+	for &(instructions, error) in invalid {
+		assert_eq!(Program::new(instructions), Err(error), "{instructions:?}");
+	}
+
+	for _ in 0..100 {
+		let code = generate_random_code();
+		let stream = crate::compile::<u8>(&code).unwrap();
+		assert_eq!(
+			Program::new(stream.instructions()),
+			Ok(Program::from(&stream))
+		);
+	}
+}
+
+#[test]
+fn stream_editing() {
+	use crate::compile::Error;
+	use crate::span::Position;
+	use crate::{InstructionStream, Program};
+
+	let stream = |code: &str| InstructionStream::<u8>::from_code_with_spans(code.bytes()).unwrap();
+	let output = |stream: &InstructionStream<u8>| {
+		Program::new(stream.instructions()).unwrap();
+		let mut out = Vec::new();
+		crate::Interpreter::build(std::io::empty(), &mut out)
+			.build()
+			.run(stream)
+			.unwrap();
+		out
+	};
+
+	let mut program = stream("[-]>+++[<++>-]<");
+	program.prepend(stream("+++"));
+	program.append(stream("[.-]"));
+	assert_eq!(output(&program), [6, 5, 4, 3, 2, 1]);
+	assert_eq!(program.spans().unwrap().len(), program.instructions().len());
+
+	program.insert(18, stream("++"));
+	assert_eq!(output(&program), [8, 7, 6, 5, 4, 3, 2, 1]);
+
+	let unchanged = program.instructions().to_vec();
+	// removing `[-]` keeps loops balanced, removing just `[-` doesn't
+	assert_eq!(
+		program.remove(3..5),
+		Err(Error::UnmatchedEnd(Position {
+			offset: 2,
+			line: 1,
+			column: 3,
+		}))
+	);
+	assert_eq!(program.instructions(), unchanged);
+	program.remove(3..6).unwrap();
+	assert_eq!(output(&program).len(), 11);
+	assert!(program.remove(..).is_ok());
+	assert!(program.instructions().is_empty());
+
+	let program = InstructionStream::<u8>::from_code("+++".bytes())
+		.unwrap()
+		.concat(stream("[>++<-]>."));
+	assert_eq!(output(&program), [6]);
+	assert_eq!(program.spans(), None);
+}
+
+#[test]
+fn loop_tree() {
+	use crate::compile::tree::{self, Block, Loop, Node, Op, Rewrite, Visit};
+	use crate::compile::{OptimizeOptions, Pass};
+	use crate::span::Span;
+	use crate::{Instruction, InstructionStream};
+
+	struct Nesting {
+		current: usize,
+		max: usize,
+		ops: usize,
+	}
+
+	impl Visit<u8> for Nesting {
+		fn visit_op(&mut self, _op: &Op<u8>) {
+			self.ops += 1;
+		}
+
+		fn visit_loop(&mut self, node: &Loop<u8>) {
+			self.current += 1;
+			self.max = self.max.max(self.current);
+			tree::walk(self, &node.body);
+			self.current -= 1;
+		}
+	}
+
+	/// Replaces `[-]` with `Set(0)`.
+	struct Clear;
+
+	impl Rewrite<u8> for Clear {
+		fn rewrite_loop(&mut self, mut node: Loop<u8>) -> Block<u8> {
+			if let [Node::Op(Op {
+				instruction: Instruction::Dec(amount),
+				..
+			})] = node.body[..]
+			{
+				if amount.get() == 1 {
+					return vec![Node::Op(Op {
+						instruction: Instruction::Set(0),
+						span: node
+							.start_span
+							.zip(node.end_span)
+							.map(|(start, end)| start.merge(end)),
+					})];
+				}
+			}
+			node.body = tree::rewrite(self, node.body);
+			vec![Node::Loop(node)]
+		}
+	}
+
+	impl Pass<u8> for Clear {
+		fn name(&self) -> &'static str {
+			"clear"
+		}
+
+		fn run(&mut self, instructions: &mut Vec<Instruction<u8>>, spans: Option<&mut Vec<Span>>) {
+			let block = tree::build(instructions, spans.as_deref().map(Vec::as_slice));
+			let (rewritten, rewritten_spans) = tree::flatten(tree::rewrite(self, block));
+			*instructions = rewritten;
+			if let Some(spans) = spans {
+				*spans = rewritten_spans.unwrap();
+			}
+		}
+	}
+
+	let stream = InstructionStream::<u8>::from_code_with_spans("+[>[-]<[->+[-]<]]".bytes()).unwrap();
+	let tree = stream.to_tree();
+	let mut nesting = Nesting {
+		current: 0,
+		max: 0,
+		ops: 0,
+	};
+	tree::walk(&mut nesting, &tree);
+	assert_eq!((nesting.max, nesting.ops), (3, 9));
+
+	let cleared = InstructionStream::from_tree(tree::rewrite(&mut Clear, tree)).unwrap();
+	assert_eq!(
+		cleared.instructions().len(),
+		stream.instructions().len() - 4
+	);
+	assert_eq!(cleared.instructions()[3], Instruction::Set(0));
+	assert_eq!(cleared.spans().unwrap()[3].range(), 3..6);
+
+	let mut stream = InstructionStream::<u8>::from_code_with_spans("+[>[-]<-]".bytes()).unwrap();
+	stream
+		.optimize_with(&mut OptimizeOptions::none().pass(Clear))
+		.unwrap();
+	assert_eq!(stream.instructions()[3], Instruction::Set(0));
+
+	for _ in 0..100 {
+		let code = generate_random_code();
+		let stream = InstructionStream::<u8>::from_code_with_spans(code.bytes()).unwrap();
+		let rebuilt = InstructionStream::from_tree(stream.to_tree()).unwrap();
+		assert_eq!(rebuilt.instructions(), stream.instructions());
+		assert_eq!(rebuilt.spans(), stream.spans());
+
+		let optimized = crate::compile::<u8>(&code).unwrap();
+		let rebuilt = InstructionStream::from_tree(optimized.to_tree()).unwrap();
+		assert_eq!(rebuilt.instructions(), optimized.instructions());
+		assert_eq!(rebuilt.spans(), None);
+	}
+
+	assert!(InstructionStream::<u8>::from_tree(vec![Node::Op(Op {
+		instruction: Instruction::LoopEnd(0),
+		span: None,
+	})])
+	.is_err());
+}
+
+#[test]
+fn loop_analysis() {
+	use crate::compile::analysis::LoopSummary;
+	use crate::InstructionStream;
+
+	let loops = |code: &str| {
+		InstructionStream::<u8>::from_code(code.bytes())
+			.unwrap()
+			.loops()
+			.collect::<Vec<_>>()
+	};
+
+	assert_eq!(
+		loops("+[->+>++<<]>[-<,>]>[>]"),
+		[
+			LoopSummary {
+				start: 1,
+				end: 10,
+				depth: 0,
+				pointer_delta: Some(0),
+				cells: Some(vec![0, 1, 2]),
+				does_io: false,
+				loop_cell_delta: Some(255),
+			},
+			LoopSummary {
+				start: 12,
+				end: 17,
+				depth: 0,
+				pointer_delta: Some(0),
+				cells: Some(vec![-1, 0]),
+				does_io: true,
+				loop_cell_delta: Some(255),
+			},
+			LoopSummary {
+				start: 19,
+				end: 21,
+				depth: 0,
+				pointer_delta: Some(1),
+				cells: Some(vec![]),
+				does_io: false,
+				loop_cell_delta: None,
+			},
+		]
+	);
+
+	let nested = loops("+[>[-]<-[+++.]+++]");
+	assert_eq!(
+		nested
+			.iter()
+			.map(|summary| (summary.start, summary.depth))
+			.collect::<Vec<_>>(),
+		[(1, 0), (3, 1), (8, 1)]
+	);
+	assert_eq!(nested[0].cells, Some(vec![0, 1]));
+	assert!(nested[0].does_io && !nested[1].does_io);
+	// the second inner loop changes the outer loop's cell
+	assert_eq!(nested[0].loop_cell_delta, None);
+	assert_eq!(nested[2].loop_cell_delta, Some(3));
+
+	let scanning = loops("+[[>]-]");
+	assert_eq!(scanning[0].pointer_delta, None);
+	assert_eq!(scanning[0].cells, None);
+	assert_eq!(scanning[0].loop_cell_delta, None);
+
+	let optimized = crate::compile::<u8>(",[.>[>]<,]").unwrap();
+	let summaries = optimized.loops().collect::<Vec<_>>();
+	assert_eq!(summaries.len(), 1);
+	assert!(summaries[0].does_io);
+	assert_eq!(summaries[0].pointer_delta, None);
+}
+
 #[test]
 fn optimization_fuzzer() {
 	const NUM_FUZZES: usize = 250;